@@ -0,0 +1,108 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! The time taken by an animation frame round trip, through a session run on the main thread.
+//! Content requests a frame, renders for 1ms, and submits it, while the main thread runs
+//! `MainThreadRegistry::run_one_frame`. The session waits for each submission with a
+//! timeout, so any delay in waking up for it is added to every frame.
+
+use criterion::criterion_group;
+use criterion::criterion_main;
+use criterion::Criterion;
+
+use euclid::default::Size2D;
+use euclid::Rect;
+use euclid::RigidTransform3D;
+use euclid::Transform3D;
+
+use std::thread;
+use std::time::Duration;
+
+use webxr_api::Device;
+use webxr_api::EndReason;
+use webxr_api::Event;
+use webxr_api::Floor;
+use webxr_api::Frame;
+use webxr_api::InputSource;
+use webxr_api::MainThreadRegistry;
+use webxr_api::MainThreadWaker;
+use webxr_api::Native;
+use webxr_api::Quitter;
+use webxr_api::Sender;
+use webxr_api::SessionThread;
+use webxr_api::View;
+use webxr_api::Views;
+
+const RENDER_TIME: Duration = Duration::from_millis(1);
+
+struct Waker;
+
+impl MainThreadWaker for Waker {
+    fn clone_box(&self) -> Box<dyn MainThreadWaker> {
+        Box::new(Waker)
+    }
+    fn wake(&self) {}
+}
+
+// A device which returns frames straight away, and throws away what is rendered.
+struct BenchDevice;
+
+impl Device for BenchDevice {
+    fn floor_transform(&self) -> RigidTransform3D<f32, Native, Floor> {
+        RigidTransform3D::identity()
+    }
+
+    fn views(&self) -> Views {
+        Views::Mono(View {
+            transform: RigidTransform3D::identity(),
+            projection: Transform3D::identity(),
+            viewport: Rect::zero(),
+        })
+    }
+
+    fn wait_for_animation_frame(&mut self) -> Frame {
+        Frame {
+            transform: RigidTransform3D::identity(),
+            inputs: vec![],
+        }
+    }
+
+    fn render_animation_frame(&mut self, _: u32, _: Size2D<i32>, _: Option<gleam::gl::GLsync>) {}
+
+    fn supports_pixel_buffers(&self) -> bool {
+        true
+    }
+
+    fn initial_inputs(&self) -> Vec<InputSource> {
+        vec![]
+    }
+
+    fn set_event_dest(&mut self, _: Sender<Event>) {}
+
+    fn quit(&mut self, _: EndReason) {}
+
+    fn set_quitter(&mut self, _: Quitter) {}
+}
+
+fn frame_round_trip(c: &mut Criterion) {
+    let mut registry = MainThreadRegistry::new(Box::new(Waker)).unwrap();
+    let mut session_thread = SessionThread::new(BenchDevice, None, None).unwrap();
+    let mut session = session_thread.new_session();
+    registry.run_on_main_thread(session_thread);
+    thread::spawn(move || {
+        let (sender, receiver) = webxr_api::channel().unwrap();
+        loop {
+            session.request_animation_frame(sender.clone());
+            if receiver.recv().is_err() {
+                break;
+            }
+            thread::sleep(RENDER_TIME);
+            session.render_animation_frame();
+        }
+    });
+    c.bench_function("frame round trip", |b| b.iter(|| registry.run_one_frame()));
+}
+
+criterion_group!(benches, frame_round_trip);
+criterion_main!(benches);
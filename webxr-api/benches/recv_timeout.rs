@@ -0,0 +1,65 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! How long it takes to wake up for an IPC message which arrives while waiting,
+//! when polling with `recv_timeout`, and with a `DeadlineReceiver`.
+//! The message is sent 1ms into a 5ms wait. If it arrives late,
+//! it is still waited for, so that it isn't left for the next iteration.
+
+use criterion::criterion_group;
+use criterion::criterion_main;
+use criterion::Criterion;
+
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use webxr_api::DeadlineReceiver;
+use webxr_api::Receiver;
+
+const DELAY: Duration = Duration::from_millis(1);
+const TIMEOUT: Duration = Duration::from_millis(5);
+
+// Each time it is told to, sends a message after the delay.
+fn delayed_sender() -> (mpsc::Sender<()>, Receiver<u32>) {
+    let (go, ready) = mpsc::channel();
+    let (sender, receiver) = webxr_api::channel().unwrap();
+    thread::spawn(move || {
+        while ready.recv().is_ok() {
+            thread::sleep(DELAY);
+            let _ = sender.send(0);
+        }
+    });
+    (go, receiver)
+}
+
+#[allow(deprecated)]
+fn polling(c: &mut Criterion) {
+    let (go, receiver) = delayed_sender();
+    c.bench_function("polling recv_timeout", |b| {
+        b.iter(|| {
+            go.send(()).unwrap();
+            webxr_api::recv_timeout(&receiver, TIMEOUT)
+                .or_else(|_| receiver.recv())
+                .unwrap()
+        })
+    });
+}
+
+fn deadline(c: &mut Criterion) {
+    let (go, receiver) = delayed_sender();
+    let receiver = DeadlineReceiver::new(receiver);
+    c.bench_function("DeadlineReceiver::recv_timeout", |b| {
+        b.iter(|| {
+            go.send(()).unwrap();
+            receiver
+                .recv_timeout(TIMEOUT)
+                .or_else(|_| receiver.recv())
+                .unwrap()
+        })
+    });
+}
+
+criterion_group!(benches, polling, deadline);
+criterion_main!(benches);
@@ -4,12 +4,15 @@
 
 //! Traits to be implemented by backends
 
+use crate::DeviceNotifier;
+use crate::EndReason;
 use crate::Error;
 use crate::Event;
 use crate::Floor;
 use crate::Frame;
 use crate::InputSource;
 use crate::Native;
+use crate::PixelBuffer;
 use crate::Quitter;
 use crate::Sender;
 use crate::Session;
@@ -24,10 +27,97 @@ use euclid::Size2D;
 
 use gleam::gl::GLsync;
 
+#[cfg(feature = "serialize")]
+use serde::{Deserialize, Serialize};
+
 /// A trait for discovering XR devices
 pub trait Discovery: 'static {
     fn request_session(&mut self, mode: SessionMode, xr: SessionBuilder) -> Result<Session, Error>;
     fn supports_session(&self, mode: SessionMode) -> bool;
+
+    /// A description of the device, for embedders choosing between devices.
+    fn device_info(&self) -> DeviceInfo {
+        DeviceInfo::new("Unknown device", "", self)
+    }
+
+    /// Called when the discovery is registered, with a notifier
+    /// for when the device is connected or disconnected.
+    fn set_device_notifier(&mut self, _notifier: DeviceNotifier) {}
+
+    /// Called when the registry shuts down, after its sessions have ended,
+    /// to join any threads the discovery started.
+    fn shutdown(&mut self) {}
+}
+
+impl Discovery for Box<dyn Discovery> {
+    fn request_session(&mut self, mode: SessionMode, xr: SessionBuilder) -> Result<Session, Error> {
+        (**self).request_session(mode, xr)
+    }
+
+    fn supports_session(&self, mode: SessionMode) -> bool {
+        (**self).supports_session(mode)
+    }
+
+    fn device_info(&self) -> DeviceInfo {
+        (**self).device_info()
+    }
+
+    fn set_device_notifier(&mut self, notifier: DeviceNotifier) {
+        (**self).set_device_notifier(notifier)
+    }
+
+    fn shutdown(&mut self) {
+        (**self).shutdown()
+    }
+}
+
+/// An identifier for a device registered with a `MainThreadRegistry`.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct DeviceId(pub u32);
+
+/// A change to the devices available, which content should
+/// respond to by checking which sessions are supported.
+/// https://immersive-web.github.io/webxr/#eventdef-xrsystem-devicechange
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub enum DeviceChange {
+    Connected(DeviceId),
+    Disconnected(DeviceId),
+}
+
+/// A description of a device.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct DeviceInfo {
+    pub name: String,
+    pub vendor: String,
+    pub modes: Vec<SessionMode>,
+    /// WebXR feature descriptors, such as "local-floor"
+    /// https://immersive-web.github.io/webxr/#feature-descriptor
+    pub features: Vec<String>,
+}
+
+impl DeviceInfo {
+    /// A description of a discovery's device, with the modes it supports and the
+    /// reference spaces every device supports. Backends can add more features.
+    pub fn new<D: Discovery + ?Sized>(name: &str, vendor: &str, discovery: &D) -> DeviceInfo {
+        let modes = [
+            SessionMode::Inline,
+            SessionMode::ImmersiveVR,
+            SessionMode::ImmersiveAR,
+        ];
+        DeviceInfo {
+            name: name.to_owned(),
+            vendor: vendor.to_owned(),
+            modes: modes
+                .iter()
+                .cloned()
+                .filter(|mode| discovery.supports_session(*mode))
+                .collect(),
+            features: vec!["viewer".into(), "local".into(), "local-floor".into()],
+        }
+    }
 }
 
 /// A trait for using an XR device
@@ -62,6 +152,42 @@ pub trait Device: 'static {
         sync: Option<GLsync>,
     );
 
+    /// Whether the device accepts a 2D texture array with one layer per view,
+    /// as used by OVR_multiview.
+    fn supports_multiview(&self) -> bool {
+        false
+    }
+
+    /// This method should render a GL 2D texture array to the device,
+    /// with layer 0 for the left (or mono) view and layer 1 for the right view.
+    /// Each layer has the given size. It is only called if `supports_multiview` is true,
+    /// and has the same synchronization requirements as `render_animation_frame`.
+    fn render_multiview_animation_frame(
+        &mut self,
+        _texture_id: u32,
+        _size: UntypedSize2D<i32>,
+        _sync: Option<GLsync>,
+    ) {
+    }
+
+    /// Whether the device can display pixel buffers, for content rendered without GL.
+    fn supports_pixel_buffers(&self) -> bool {
+        false
+    }
+
+    /// This method should render a CPU pixel buffer to the device.
+    /// It is only called if `supports_pixel_buffers` is true.
+    fn render_pixel_frame(&mut self, _pixels: &PixelBuffer) {}
+
+    /// Whether the device honours the foveation level set by content.
+    fn supports_foveation(&self) -> bool {
+        false
+    }
+
+    /// Sets the level of fixed foveated rendering, from 0.0 (none) to 1.0 (maximum).
+    /// Devices which do not support foveation can ignore this.
+    fn set_foveation_level(&mut self, _level: f32) {}
+
     /// Inputs registered with the device on initialization. More may be added, which
     /// should be communicated through a yet-undecided event mechanism
     fn initial_inputs(&self) -> Vec<InputSource>;
@@ -69,8 +195,81 @@ pub trait Device: 'static {
     /// Sets the event handling channel
     fn set_event_dest(&mut self, dest: Sender<Event>);
 
-    /// Quit the session
-    fn quit(&mut self);
+    /// Quit the session, which should send content a `SessionEnd` event with the reason.
+    /// GL resources should be released when the device is dropped.
+    fn quit(&mut self, reason: EndReason);
 
     fn set_quitter(&mut self, quitter: Quitter);
 }
+
+impl Device for Box<dyn Device> {
+    fn floor_transform(&self) -> RigidTransform3D<f32, Native, Floor> {
+        (**self).floor_transform()
+    }
+
+    fn views(&self) -> Views {
+        (**self).views()
+    }
+
+    fn recommended_framebuffer_resolution(&self) -> Size2D<i32, Viewport> {
+        (**self).recommended_framebuffer_resolution()
+    }
+
+    fn wait_for_animation_frame(&mut self) -> Frame {
+        (**self).wait_for_animation_frame()
+    }
+
+    fn render_animation_frame(
+        &mut self,
+        texture_id: u32,
+        size: UntypedSize2D<i32>,
+        sync: Option<GLsync>,
+    ) {
+        (**self).render_animation_frame(texture_id, size, sync)
+    }
+
+    fn supports_multiview(&self) -> bool {
+        (**self).supports_multiview()
+    }
+
+    fn render_multiview_animation_frame(
+        &mut self,
+        texture_id: u32,
+        size: UntypedSize2D<i32>,
+        sync: Option<GLsync>,
+    ) {
+        (**self).render_multiview_animation_frame(texture_id, size, sync)
+    }
+
+    fn supports_pixel_buffers(&self) -> bool {
+        (**self).supports_pixel_buffers()
+    }
+
+    fn render_pixel_frame(&mut self, pixels: &PixelBuffer) {
+        (**self).render_pixel_frame(pixels)
+    }
+
+    fn supports_foveation(&self) -> bool {
+        (**self).supports_foveation()
+    }
+
+    fn set_foveation_level(&mut self, level: f32) {
+        (**self).set_foveation_level(level)
+    }
+
+    fn initial_inputs(&self) -> Vec<InputSource> {
+        (**self).initial_inputs()
+    }
+
+    fn set_event_dest(&mut self, dest: Sender<Event>) {
+        (**self).set_event_dest(dest)
+    }
+
+    fn quit(&mut self, reason: EndReason) {
+        (**self).quit(reason)
+    }
+
+    fn set_quitter(&mut self, quitter: Quitter) {
+        (**self).set_quitter(quitter)
+    }
+}
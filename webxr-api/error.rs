@@ -2,16 +2,53 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
 
-#[cfg(feature = "ipc")]
-use serde::{Deserialize, Serialize};
+use crate::SessionMode;
 
-/// Errors that can be produced by XR.
+#[cfg(feature = "serialize")]
+use serde::{Deserialize, Serialize};
 
-// TODO: this is currently incomplete!
+use std::fmt;
 
-#[derive(Debug)]
-#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+/// Errors that can be produced by XR.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 pub enum Error {
+    /// No device is available, for example no mock device could be connected
     NoMatchingDevice,
+    /// A channel to another thread or process failed
     CommunicationError,
+    /// No device supports sessions of this mode
+    UnsupportedMode(SessionMode),
+    /// The device doesn't support a feature the session needs
+    UnsupportedFeature(String),
+    /// An immersive session is already running
+    SessionAlreadyActive,
+    /// Neither WebGL nor pixel buffers have been configured for rendering
+    NoWebGL,
+    /// The device has been disconnected
+    DeviceDisconnected,
+    /// The backend failed to initialize the device
+    DeviceInitFailed(String),
+    /// The user or embedder denied permission for the session
+    PermissionDenied,
 }
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::NoMatchingDevice => write!(f, "No matching XR device"),
+            Error::CommunicationError => write!(f, "Failed to communicate with XR thread"),
+            Error::UnsupportedMode(mode) => write!(f, "Session mode {:?} is not supported", mode),
+            Error::UnsupportedFeature(feature) => {
+                write!(f, "XR device doesn't support {}", feature)
+            }
+            Error::SessionAlreadyActive => write!(f, "An immersive session is already active"),
+            Error::NoWebGL => write!(f, "No WebGL or pixel buffers configured"),
+            Error::DeviceDisconnected => write!(f, "XR device was disconnected"),
+            Error::DeviceInitFailed(msg) => write!(f, "Failed to initialize XR device: {}", msg),
+            Error::PermissionDenied => write!(f, "Permission to start session was denied"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
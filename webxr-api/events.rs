@@ -7,20 +7,37 @@ use crate::InputSource;
 use crate::Sender;
 
 #[derive(Copy, Clone, Debug)]
-#[cfg_attr(feature = "ipc", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
 pub enum Event {
     /// Input source connected
     AddInput(InputSource),
     /// Input source disconnected
     RemoveInput(InputId),
-    /// Session ended by device
-    SessionEnd,
+    /// Session ended, by content or by the device
+    SessionEnd(EndReason),
     /// Session focused/blurred/etc
     VisibilityChange(Visibility),
+    /// Selection or squeeze on an input source
+    Select(InputId, SelectKind, SelectEvent),
+}
+
+/// Why a session ended
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub enum EndReason {
+    /// Content ended the session or dropped it, the user closed the window it was
+    /// presenting in, or the embedder shut down the registry
+    UserRequested,
+    /// The device was disconnected
+    DeviceLost,
+    /// The device ended the session itself, for example when a replayed trace runs out
+    DeviceInitiated,
+    /// The session failed
+    Error,
 }
 
 #[derive(Copy, Clone, Debug)]
-#[cfg_attr(feature = "ipc", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
 pub enum Visibility {
     /// Session fully displayed to user
     Visible,
@@ -30,6 +47,26 @@ pub enum Visibility {
     Hidden,
 }
 
+/// The kind of primary action on an input source
+/// https://immersive-web.github.io/webxr/#primary-action
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub enum SelectKind {
+    Select,
+    Squeeze,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub enum SelectEvent {
+    /// The action has started
+    Start,
+    /// The action was cancelled without completing
+    End,
+    /// The action completed; this also ends it
+    Select,
+}
+
 /// Convenience structure for buffering up events
 /// when no event callback has been set
 pub enum EventBuffer {
@@ -12,7 +12,7 @@ use euclid::RigidTransform3D;
 /// https://www.w3.org/TR/webxr/#xrframe
 // TODO: other fields?
 #[derive(Clone, Debug)]
-#[cfg_attr(feature = "ipc", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
 pub struct Frame {
     /// The transform from the viewer to native coordinates
     ///
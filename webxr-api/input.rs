@@ -8,11 +8,11 @@ use crate::Native;
 use euclid::RigidTransform3D;
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
-#[cfg_attr(feature = "ipc", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
 pub struct InputId(pub u32);
 
 #[derive(Copy, Clone, Debug)]
-#[cfg_attr(feature = "ipc", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
 pub enum Handedness {
     None,
     Left,
@@ -20,7 +20,7 @@ pub enum Handedness {
 }
 
 #[derive(Copy, Clone, Debug)]
-#[cfg_attr(feature = "ipc", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
 pub enum TargetRayMode {
     Gaze,
     TrackedPointer,
@@ -28,7 +28,7 @@ pub enum TargetRayMode {
 }
 
 #[derive(Copy, Clone, Debug)]
-#[cfg_attr(feature = "ipc", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
 pub struct InputSource {
     pub handedness: Handedness,
     pub target_ray_mode: TargetRayMode,
@@ -36,7 +36,7 @@ pub struct InputSource {
 }
 
 #[derive(Clone, Debug)]
-#[cfg_attr(feature = "ipc", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
 pub struct InputFrame {
     pub id: InputId,
     pub target_ray_origin: RigidTransform3D<f32, Input, Native>,
@@ -10,18 +10,30 @@ mod events;
 mod frame;
 mod input;
 mod mock;
+mod permissions;
+mod pixels;
 mod registry;
 mod session;
+#[cfg(feature = "shmem")]
+mod shmem;
+#[cfg(feature = "async")]
+mod stream;
 mod view;
 mod webgl;
 
 pub use device::Device;
+pub use device::DeviceChange;
+pub use device::DeviceId;
+pub use device::DeviceInfo;
 pub use device::Discovery;
 
 pub use error::Error;
 
+pub use events::EndReason;
 pub use events::Event;
 pub use events::EventBuffer;
+pub use events::SelectEvent;
+pub use events::SelectKind;
 pub use events::Visibility;
 
 pub use frame::Frame;
@@ -35,23 +47,57 @@ pub use input::TargetRayMode;
 pub use mock::MockDeviceInit;
 pub use mock::MockDeviceMsg;
 pub use mock::MockDiscovery;
+pub use mock::MockFrameCapture;
 pub use mock::MockInputInit;
 pub use mock::MockInputMsg;
+pub use mock::MockViewCapture;
 
+pub use permissions::MockPermissions;
+pub use permissions::PermissionApi;
+pub use permissions::PermissionRequest;
+
+pub use pixels::PixelBuffer;
+pub use pixels::PixelBufferApi;
+pub use pixels::PixelBufferId;
+
+#[cfg(feature = "async")]
+pub use registry::AsyncRegistry;
+pub use registry::DeviceNotifier;
 pub use registry::MainThreadRegistry;
 pub use registry::MainThreadWaker;
+pub use registry::PermissionResponder;
 pub use registry::Registry;
+pub use registry::SelectionPolicy;
 
+pub use session::DeviceWrapper;
+#[cfg(feature = "async")]
+pub use session::FrameStream;
 pub use session::HighResTimeStamp;
 pub use session::MainThreadSession;
 pub use session::Quitter;
 pub use session::Session;
 pub use session::SessionBuilder;
+pub use session::SessionInit;
 pub use session::SessionMode;
 pub use session::SessionThread;
 
+#[cfg(feature = "shmem")]
+pub use shmem::FrameRingHandle;
+#[cfg(feature = "shmem")]
+pub use shmem::FrameRingReader;
+#[cfg(feature = "shmem")]
+pub use shmem::FrameRingWriter;
+#[cfg(feature = "shmem")]
+pub use shmem::SharedFrame;
+
+#[cfg(feature = "async")]
+pub use stream::RecvFuture;
+#[cfg(feature = "async")]
+pub use stream::RecvStream;
+
 pub use view::Display;
 pub use view::Floor;
+pub use view::Fov;
 pub use view::Input;
 pub use view::LeftEye;
 pub use view::Native;
@@ -68,6 +114,7 @@ pub use webgl::WebGLTextureId;
 #[cfg(feature = "ipc")]
 use std::thread;
 
+use std::sync::mpsc;
 use std::time::Duration;
 
 #[cfg(feature = "ipc")]
@@ -83,16 +130,19 @@ pub use ipc_channel::ipc::channel;
 pub use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender};
 
 #[cfg(not(feature = "ipc"))]
-fn channel<T>() -> Result<(Sender<T>, Receiver<T>), ()> {
+#[allow(clippy::result_unit_err)]
+pub fn channel<T>() -> Result<(Sender<T>, Receiver<T>), ()> {
     Ok(std::sync::mpsc::channel())
 }
 
 #[cfg(not(feature = "ipc"))]
+#[deprecated(note = "Use DeadlineReceiver instead")]
 pub fn recv_timeout<T>(receiver: &Receiver<T>, timeout: Duration) -> Result<T, RecvTimeoutError> {
     receiver.recv_timeout(timeout)
 }
 
 #[cfg(feature = "ipc")]
+#[deprecated(note = "Use DeadlineReceiver instead, which doesn't poll")]
 pub fn recv_timeout<T>(receiver: &Receiver<T>, timeout: Duration) -> Result<T, ipc_channel::Error>
 where
     T: serde::Serialize + for<'a> serde::Deserialize<'a>,
@@ -104,7 +154,52 @@ where
             return Ok(msg);
         }
         thread::sleep(delay);
-        delay = delay * 2;
+        delay *= 2;
     }
     receiver.try_recv()
 }
+
+/// A receiver which can block with a timeout, which IPC receivers can't do on their own.
+/// IPC messages are routed through a thread into an in-process channel which can.
+/// That thread exits once every sender has been dropped, or when a message
+/// arrives after this receiver has been dropped.
+pub struct DeadlineReceiver<T> {
+    receiver: mpsc::Receiver<T>,
+}
+
+#[cfg(not(feature = "ipc"))]
+impl<T> DeadlineReceiver<T> {
+    pub fn new(receiver: Receiver<T>) -> DeadlineReceiver<T> {
+        DeadlineReceiver { receiver }
+    }
+}
+
+#[cfg(feature = "ipc")]
+impl<T> DeadlineReceiver<T>
+where
+    T: 'static + Send + serde::Serialize + for<'a> serde::Deserialize<'a>,
+{
+    pub fn new(receiver: Receiver<T>) -> DeadlineReceiver<T> {
+        let (sender, local_receiver) = mpsc::channel();
+        thread::spawn(move || {
+            while let Ok(msg) = receiver.recv() {
+                if sender.send(msg).is_err() {
+                    break;
+                }
+            }
+        });
+        DeadlineReceiver {
+            receiver: local_receiver,
+        }
+    }
+}
+
+impl<T> DeadlineReceiver<T> {
+    pub fn recv(&self) -> Result<T, mpsc::RecvError> {
+        self.receiver.recv()
+    }
+
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, mpsc::RecvTimeoutError> {
+        self.receiver.recv_timeout(timeout)
+    }
+}
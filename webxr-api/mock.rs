@@ -11,14 +11,18 @@ use crate::InputId;
 use crate::InputSource;
 use crate::Native;
 use crate::Receiver;
+use crate::SelectEvent;
+use crate::SelectKind;
 use crate::Sender;
 use crate::TargetRayMode;
 use crate::Viewer;
+use crate::Viewport;
 use crate::Views;
 
+use euclid::Rect;
 use euclid::RigidTransform3D;
 
-#[cfg(feature = "ipc")]
+#[cfg(feature = "serialize")]
 use serde::{Deserialize, Serialize};
 
 /// A trait for discovering mock XR devices
@@ -31,7 +35,7 @@ pub trait MockDiscovery: 'static {
 }
 
 #[derive(Clone, Debug)]
-#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 pub struct MockDeviceInit {
     pub floor_origin: RigidTransform3D<f32, Floor, Native>,
     pub supports_immersive: bool,
@@ -49,22 +53,46 @@ pub enum MockDeviceMsg {
     MessageInputSource(InputId, MockInputMsg),
     Focus,
     Blur,
+    /// Get the foveation level requested for each frame rendered so far, oldest first.
+    /// Devices may only keep the levels of recent frames.
+    GetFoveationLevels(Sender<Vec<f32>>),
+    /// Start keeping the pixels of the last N frames rendered, or stop if N is 0
+    CaptureFrames(usize),
+    /// Get the frames captured so far, oldest first
+    GetCapturedFrames(Sender<Vec<MockFrameCapture>>),
     Disconnect(Sender<()>),
 }
 
+/// The pixels rendered to a mock device for one frame
 #[derive(Clone, Debug)]
-#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct MockFrameCapture {
+    pub views: Vec<MockViewCapture>,
+}
+
+/// The pixels rendered to one view's viewport, as RGBA8 rows from bottom to top
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct MockViewCapture {
+    pub viewport: Rect<i32, Viewport>,
+    pub pixels: Vec<u8>,
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 pub struct MockInputInit {
     pub source: InputSource,
     pub pointer_origin: RigidTransform3D<f32, Input, Native>,
 }
 
 #[derive(Debug)]
-#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 pub enum MockInputMsg {
     SetHandedness(Handedness),
     SetTargetRayMode(TargetRayMode),
     SetPointerOrigin(RigidTransform3D<f32, Input, Native>),
+    /// Start, end or complete a select or squeeze
+    TriggerSelect(SelectKind, SelectEvent),
     Disconnect,
     Reconnect,
 }
@@ -0,0 +1,59 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Asking the user for permission to start sessions.
+//! https://immersive-web.github.io/webxr/#permissions
+
+use crate::PermissionResponder;
+use crate::SessionMode;
+
+/// A request for permission to start a session, with the features content asked for.
+#[derive(Clone, Debug)]
+pub struct PermissionRequest {
+    pub mode: SessionMode,
+    pub required_features: Vec<String>,
+    pub optional_features: Vec<String>,
+}
+
+/// A trait for the embedder to prompt the user before a session starts.
+/// This runs on the main thread, but the response can be sent later from any thread,
+/// so the embedder can show a prompt without blocking the registry.
+pub trait PermissionApi: 'static {
+    fn request_permission(&mut self, request: PermissionRequest, responder: PermissionResponder);
+}
+
+/// A permission API for tests, which answers immediately,
+/// granting every feature except those it was told to deny.
+#[derive(Clone, Debug, Default)]
+pub struct MockPermissions {
+    deny_sessions: bool,
+    denied_features: Vec<String>,
+}
+
+impl MockPermissions {
+    /// Deny every session.
+    pub fn deny_sessions(&mut self) {
+        self.deny_sessions = true;
+    }
+
+    /// Deny a feature, so sessions which require it are denied.
+    pub fn deny_feature(&mut self, feature: &str) {
+        self.denied_features.push(feature.to_owned());
+    }
+}
+
+impl PermissionApi for MockPermissions {
+    fn request_permission(&mut self, request: PermissionRequest, responder: PermissionResponder) {
+        if self.deny_sessions {
+            return responder.deny();
+        }
+        let granted = request
+            .required_features
+            .into_iter()
+            .chain(request.optional_features)
+            .filter(|feature| !self.denied_features.contains(feature))
+            .collect();
+        responder.grant(granted)
+    }
+}
@@ -0,0 +1,34 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! The functionality needed by WebXR for content rendered without GL.
+
+use euclid::default::Size2D as UntypedSize2D;
+
+#[cfg(feature = "serialize")]
+use serde::{Deserialize, Serialize};
+
+pub type PixelBufferId = usize;
+
+/// An image in CPU memory, as RGBA8 rows from bottom to top.
+/// It covers all of the views' viewports, in the same way as a WebGL texture would.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct PixelBuffer {
+    pub size: UntypedSize2D<i32>,
+    pub pixels: Vec<u8>,
+}
+
+/// A trait to get access to the pixel buffers of producers which do not use GL,
+/// such as software renderers. This is the equivalent of `WebGLExternalImageApi`.
+pub trait PixelBufferApi: Send {
+    /// Lock the pixel buffer, and get back its current contents.
+    fn lock(&self, id: PixelBufferId) -> Option<PixelBuffer>;
+
+    /// Unlock the pixel buffer.
+    fn unlock(&self, id: PixelBufferId);
+
+    /// Workaround for Clone not being object-safe
+    fn clone_box(&self) -> Box<dyn PixelBufferApi>;
+}
@@ -2,22 +2,45 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
 
+use crate::DeadlineReceiver;
+use crate::DeviceChange;
+use crate::DeviceId;
+use crate::DeviceInfo;
 use crate::Discovery;
+use crate::EndReason;
 use crate::Error;
 use crate::MainThreadSession;
 use crate::MockDeviceInit;
 use crate::MockDeviceMsg;
 use crate::MockDiscovery;
+use crate::PermissionApi;
+use crate::PermissionRequest;
+use crate::PixelBufferApi;
 use crate::Receiver;
 use crate::Sender;
 use crate::Session;
 use crate::SessionBuilder;
+use crate::SessionInit;
 use crate::SessionMode;
 use crate::WebGLExternalImageApi;
 
+use crate::session::SpawnedSession;
+
+#[cfg(feature = "async")]
+use crate::RecvStream;
+
 #[cfg(feature = "ipc")]
 use serde::{Deserialize, Serialize};
 
+#[cfg(feature = "async")]
+use std::future::Future;
+use std::sync::Arc;
+use std::sync::Weak;
+use std::time::Duration;
+
+// How long shutting down waits for each mock device to acknowledge being disconnected.
+static MOCK_DISCONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+
 #[derive(Clone)]
 #[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
 pub struct Registry {
@@ -25,14 +48,55 @@ pub struct Registry {
     waker: MainThreadWakerImpl,
 }
 
+/// Chooses which of the devices supporting a mode to try first when a session is requested.
+/// The other devices are tried afterwards, in order, if it fails.
+pub type SelectionPolicy =
+    Box<dyn FnMut(SessionMode, &[(DeviceId, DeviceInfo)]) -> Option<DeviceId>>;
+
+/// Lets a discovery tell the registry when its device is connected or disconnected.
+#[derive(Clone)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub struct DeviceNotifier {
+    id: DeviceId,
+    registry: Registry,
+}
+
+/// Answers a permission request. If it is dropped without answering, the request is denied.
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub struct PermissionResponder {
+    id: u32,
+    registry: Option<Registry>,
+}
+
+// A session request waiting for permission.
+struct PendingSession {
+    id: u32,
+    mode: SessionMode,
+    init: SessionInit,
+    dest: Sender<Result<Session, Error>>,
+}
+
 pub struct MainThreadRegistry {
-    discoveries: Vec<Box<dyn Discovery>>,
+    discoveries: Vec<(DeviceId, Box<dyn Discovery>)>,
+    subscribers: Vec<Sender<DeviceChange>>,
+    next_device_id: u32,
+    policy: Option<SelectionPolicy>,
     sessions: Vec<Box<dyn MainThreadSession>>,
+    spawned: Vec<SpawnedSession>,
     mocks: Vec<Box<dyn MockDiscovery>>,
+    // Kept so that the mock devices can be disconnected when the registry shuts down.
+    mock_senders: Vec<(DeviceId, Sender<MockDeviceMsg>)>,
     webgl: Option<Box<dyn WebGLExternalImageApi>>,
+    pixels: Option<Box<dyn PixelBufferApi>>,
+    permissions: Option<Box<dyn PermissionApi>>,
+    pending: Vec<PendingSession>,
+    next_request_id: u32,
+    // Alive while the immersive session is running, since devices only support one at a time.
+    immersive: Weak<()>,
     sender: Sender<RegistryMsg>,
     receiver: Receiver<RegistryMsg>,
     waker: MainThreadWakerImpl,
+    shut_down: bool,
 }
 
 pub trait MainThreadWaker: 'static + Send {
@@ -86,8 +150,15 @@ impl Registry {
         self.waker.wake();
     }
 
-    pub fn request_session(&mut self, mode: SessionMode, dest: Sender<Result<Session, Error>>) {
-        let _ = self.sender.send(RegistryMsg::RequestSession(mode, dest));
+    pub fn request_session(
+        &mut self,
+        mode: SessionMode,
+        init: SessionInit,
+        dest: Sender<Result<Session, Error>>,
+    ) {
+        let _ = self
+            .sender
+            .send(RegistryMsg::RequestSession(mode, init, dest));
         self.waker.wake();
     }
 
@@ -101,6 +172,104 @@ impl Registry {
             .send(RegistryMsg::SimulateDeviceConnection(init, dest));
         self.waker.wake();
     }
+
+    /// Send a message to the given channel whenever a device is connected or disconnected.
+    pub fn subscribe_device_changes(&mut self, dest: Sender<DeviceChange>) {
+        let _ = self.sender.send(RegistryMsg::SubscribeDeviceChanges(dest));
+        self.waker.wake();
+    }
+
+    fn device_changed(&mut self, change: DeviceChange) {
+        let _ = self.sender.send(RegistryMsg::DeviceChanged(change));
+        self.waker.wake();
+    }
+}
+
+impl PermissionResponder {
+    /// Allow the session, with the given features.
+    /// Sessions are denied if any of their required features are missing.
+    pub fn grant(mut self, features: Vec<String>) {
+        self.respond(Some(features));
+    }
+
+    pub fn deny(mut self) {
+        self.respond(None);
+    }
+
+    fn respond(&mut self, granted: Option<Vec<String>>) {
+        if let Some(registry) = self.registry.take() {
+            let _ = registry
+                .sender
+                .send(RegistryMsg::PermissionResponse(self.id, granted));
+            registry.waker.wake();
+        }
+    }
+}
+
+impl Drop for PermissionResponder {
+    fn drop(&mut self) {
+        self.respond(None);
+    }
+}
+
+impl DeviceNotifier {
+    pub fn connected(&mut self) {
+        let id = self.id;
+        self.registry.device_changed(DeviceChange::Connected(id));
+    }
+
+    /// The registry forgets the device once it is disconnected,
+    /// so it can't be connected again with the same notifier.
+    pub fn disconnected(&mut self) {
+        let id = self.id;
+        self.registry.device_changed(DeviceChange::Disconnected(id));
+    }
+}
+
+/// A registry whose requests return futures, for async content.
+#[cfg(feature = "async")]
+#[derive(Clone)]
+pub struct AsyncRegistry {
+    registry: Registry,
+}
+
+#[cfg(feature = "async")]
+impl AsyncRegistry {
+    pub fn new(registry: Registry) -> AsyncRegistry {
+        AsyncRegistry { registry }
+    }
+
+    pub fn supports_session(
+        &mut self,
+        mode: SessionMode,
+    ) -> impl Future<Output = Result<(), Error>> {
+        let channel = crate::channel().or(Err(Error::CommunicationError));
+        let future = channel.map(|(sender, receiver)| {
+            self.registry.supports_session(mode, sender);
+            crate::stream::recv_future(receiver)
+        });
+        async move { future?.await? }
+    }
+
+    pub fn request_session(
+        &mut self,
+        mode: SessionMode,
+        init: SessionInit,
+    ) -> impl Future<Output = Result<Session, Error>> {
+        let channel = crate::channel().or(Err(Error::CommunicationError));
+        let future = channel.map(|(sender, receiver)| {
+            self.registry.request_session(mode, init, sender);
+            crate::stream::recv_future(receiver)
+        });
+        async move { future?.await? }
+    }
+
+    /// A stream of the changes to the devices available.
+    pub fn device_changes(&mut self) -> Result<RecvStream<DeviceChange>, Error> {
+        let (sender, receiver) = crate::channel().or(Err(Error::CommunicationError))?;
+        self.registry.subscribe_device_changes(sender);
+        Ok(crate::stream::recv_stream(receiver))
+    }
 }
 
 impl MainThreadRegistry {
@@ -111,14 +280,27 @@ impl MainThreadRegistry {
         let mocks = Vec::new();
         let waker = MainThreadWakerImpl::new(waker)?;
         let webgl = None;
+        let pixels = None;
+        let immersive = Weak::new();
         Ok(MainThreadRegistry {
             discoveries,
+            subscribers: Vec::new(),
+            next_device_id: 0,
+            policy: None,
             sessions,
+            spawned: Vec::new(),
             mocks,
+            mock_senders: Vec::new(),
             webgl,
+            pixels,
+            permissions: None,
+            pending: Vec::new(),
+            next_request_id: 0,
+            immersive,
             sender,
             receiver,
             waker,
+            shut_down: false,
         })
     }
 
@@ -133,11 +315,94 @@ impl MainThreadRegistry {
         self.webgl = Some(webgl);
     }
 
-    pub fn register<D: Discovery>(&mut self, discovery: D) {
-        self.discoveries.push(Box::new(discovery));
+    /// Set the source of pixel buffers, for content rendered without GL.
+    pub fn set_pixel_buffers(&mut self, pixels: Box<dyn PixelBufferApi>) {
+        self.pixels = Some(pixels);
+    }
+
+    /// Set the embedder's API for asking the user for permission to start sessions.
+    /// Without one, sessions are started without asking.
+    pub fn set_permissions<P: PermissionApi>(&mut self, permissions: P) {
+        self.permissions = Some(Box::new(permissions));
+    }
+
+    /// Discoveries registered after the registry has shut down are shut down straight away,
+    /// and an error is returned.
+    pub fn register<D: Discovery>(&mut self, discovery: D) -> Result<DeviceId, Error> {
+        let mut discovery = Box::new(discovery);
+        if self.shut_down {
+            discovery.shutdown();
+            return Err(Error::CommunicationError);
+        }
+        let id = self.next_device_id();
+        discovery.set_device_notifier(self.device_notifier(id));
+        self.discoveries.push((id, discovery));
+        self.device_changed(DeviceChange::Connected(id));
+        Ok(id)
+    }
+
+    /// The registered devices, in the order they are tried by default.
+    pub fn devices(&self) -> Vec<(DeviceId, DeviceInfo)> {
+        self.discoveries
+            .iter()
+            .map(|(id, discovery)| (*id, discovery.device_info()))
+            .collect()
+    }
+
+    /// Try the given device first, when it supports the requested mode.
+    pub fn set_preferred_device(&mut self, preferred: DeviceId) {
+        self.set_selection_policy(move |_, _| Some(preferred));
+    }
+
+    pub fn set_selection_policy<P>(&mut self, policy: P)
+    where
+        P: 'static + FnMut(SessionMode, &[(DeviceId, DeviceInfo)]) -> Option<DeviceId>,
+    {
+        self.policy = Some(Box::new(policy));
+    }
+
+    fn device_notifier(&self, id: DeviceId) -> DeviceNotifier {
+        DeviceNotifier {
+            id,
+            registry: self.registry(),
+        }
+    }
+
+    fn device_changed(&mut self, change: DeviceChange) {
+        match change {
+            DeviceChange::Connected(id) if !self.has_device(id) => return,
+            DeviceChange::Disconnected(id) => match self.remove_device(id) {
+                Some(mut discovery) => discovery.shutdown(),
+                None => return,
+            },
+            _ => (),
+        }
+        self.subscribers
+            .retain(|subscriber| subscriber.send(change).is_ok());
+    }
+
+    fn has_device(&self, id: DeviceId) -> bool {
+        self.discoveries.iter().any(|(device, _)| *device == id)
+    }
+
+    fn remove_device(&mut self, id: DeviceId) -> Option<Box<dyn Discovery>> {
+        self.mock_senders.retain(|(device, _)| *device != id);
+        let index = self
+            .discoveries
+            .iter()
+            .position(|(device, _)| *device == id)?;
+        Some(self.discoveries.remove(index).1)
+    }
+
+    fn next_device_id(&mut self) -> DeviceId {
+        self.next_device_id += 1;
+        DeviceId(self.next_device_id)
     }
 
     pub fn register_mock<D: MockDiscovery>(&mut self, discovery: D) {
+        if self.shut_down {
+            return;
+        }
         self.mocks.push(Box::new(discovery));
     }
 
@@ -153,54 +418,260 @@ impl MainThreadRegistry {
             session.run_one_frame();
         }
         self.sessions.retain(|session| session.running());
+        self.spawned.retain(|spawned| !spawned.finished());
     }
 
     pub fn running(&self) -> bool {
         self.sessions.iter().any(|session| session.running())
     }
 
+    /// End every session, disconnect the mock devices, and join the threads they ran on,
+    /// releasing their GL resources. This returns once they have all finished, though mock
+    /// devices which don't acknowledge being disconnected are only waited for briefly.
+    /// No more devices can be registered or sessions started afterwards.
+    pub fn shutdown(&mut self) {
+        self.shut_down = true;
+        for session in &mut self.sessions {
+            session.quit(EndReason::UserRequested);
+        }
+        self.sessions.clear();
+        for spawned in &self.spawned {
+            spawned.quit(EndReason::UserRequested);
+        }
+        for spawned in self.spawned.drain(..) {
+            spawned.join();
+        }
+        for (_, sender) in self.mock_senders.drain(..) {
+            if let Ok((ack, receiver)) = crate::channel() {
+                if sender.send(MockDeviceMsg::Disconnect(ack)).is_ok() {
+                    let _ = DeadlineReceiver::new(receiver).recv_timeout(MOCK_DISCONNECT_TIMEOUT);
+                }
+            }
+        }
+        for (_, discovery) in &mut self.discoveries {
+            discovery.shutdown();
+        }
+        self.discoveries.clear();
+        self.mocks.clear();
+        self.subscribers.clear();
+        for pending in self.pending.drain(..) {
+            let _ = pending.dest.send(Err(Error::CommunicationError));
+        }
+    }
+
     fn handle_msg(&mut self, msg: RegistryMsg) {
         match msg {
             RegistryMsg::SupportsSession(mode, dest) => {
                 let _ = dest.send(self.supports_session(mode));
             }
-            RegistryMsg::RequestSession(mode, dest) => {
-                let _ = dest.send(self.request_session(mode));
+            RegistryMsg::RequestSession(_, _, dest) if self.shut_down => {
+                let _ = dest.send(Err(Error::CommunicationError));
+            }
+            RegistryMsg::RequestSession(mode, init, dest) => {
+                self.request_permission(mode, init, dest);
+            }
+            RegistryMsg::PermissionResponse(id, granted) => {
+                let index = match self.pending.iter().position(|pending| pending.id == id) {
+                    Some(index) => index,
+                    None => return,
+                };
+                let pending = self.pending.remove(index);
+                let result = match granted {
+                    Some(granted) => self.request_session(pending.mode, &pending.init, granted),
+                    None => Err(Error::PermissionDenied),
+                };
+                send_session(&pending.dest, result);
             }
             RegistryMsg::SimulateDeviceConnection(init, dest) => {
                 let _ = dest.send(self.simulate_device_connection(init));
             }
+            RegistryMsg::SubscribeDeviceChanges(dest) => {
+                self.subscribers.push(dest);
+            }
+            RegistryMsg::DeviceChanged(change) => {
+                self.device_changed(change);
+            }
         }
     }
 
-    fn supports_session(&mut self, mode: SessionMode) -> Result<(), Error> {
-        for discovery in &self.discoveries {
+    fn supports_session(&self, mode: SessionMode) -> Result<(), Error> {
+        for (_, discovery) in &self.discoveries {
             if discovery.supports_session(mode) {
                 return Ok(());
             }
         }
-        Err(Error::NoMatchingDevice)
+        Err(Error::UnsupportedMode(mode))
     }
 
-    fn request_session(&mut self, mode: SessionMode) -> Result<Session, Error> {
-        let webgl = self.webgl.as_ref().ok_or(Error::NoMatchingDevice)?;
-        for discovery in &mut self.discoveries {
-            let xr = SessionBuilder::new(&**webgl, &mut self.sessions);
-            if let Ok(session) = discovery.request_session(mode, xr) {
-                return Ok(session);
+    /// Whether a session could be started now, so that the user
+    /// isn't asked for permission to start one which can't be.
+    fn check_session(&self, mode: SessionMode) -> Result<(), Error> {
+        self.supports_session(mode)?;
+        if self.webgl.is_none() && self.pixels.is_none() {
+            return Err(Error::NoWebGL);
+        }
+        if mode != SessionMode::Inline && self.immersive.upgrade().is_some() {
+            return Err(Error::SessionAlreadyActive);
+        }
+        Ok(())
+    }
+
+    fn request_permission(
+        &mut self,
+        mode: SessionMode,
+        init: SessionInit,
+        dest: Sender<Result<Session, Error>>,
+    ) {
+        if let Err(err) = self.check_session(mode) {
+            let _ = dest.send(Err(err));
+            return;
+        }
+        let permissions = match self.permissions {
+            Some(ref mut permissions) => permissions,
+            None => {
+                let granted = init
+                    .required_features
+                    .iter()
+                    .chain(&init.optional_features)
+                    .cloned()
+                    .collect();
+                let result = self.request_session(mode, &init, granted);
+                send_session(&dest, result);
+                return;
             }
+        };
+        self.next_request_id += 1;
+        let id = self.next_request_id;
+        let request = PermissionRequest {
+            mode,
+            required_features: init.required_features.clone(),
+            optional_features: init.optional_features.clone(),
+        };
+        self.pending.push(PendingSession {
+            id,
+            mode,
+            init,
+            dest,
+        });
+        let responder = PermissionResponder {
+            id,
+            registry: Some(Registry {
+                sender: self.sender.clone(),
+                waker: self.waker.clone(),
+            }),
+        };
+        permissions.request_permission(request, responder);
+    }
+
+    /// Request a session from the first discovery which can provide one, starting with the
+    /// one chosen by the selection policy, if any. If they all fail, the error from the
+    /// first which supports the mode is reported.
+    /// Only one immersive session can be active, but inline sessions can run alongside it.
+    /// Another may have started while the user was asked for permission.
+    fn request_session(
+        &mut self,
+        mode: SessionMode,
+        init: &SessionInit,
+        granted: Vec<String>,
+    ) -> Result<Session, Error> {
+        if init
+            .required_features
+            .iter()
+            .any(|feature| !granted.contains(feature))
+        {
+            return Err(Error::PermissionDenied);
         }
-        Err(Error::NoMatchingDevice)
+        if self.webgl.is_none() && self.pixels.is_none() {
+            return Err(Error::NoWebGL);
+        }
+        let slot = if mode == SessionMode::Inline {
+            None
+        } else if self.immersive.upgrade().is_some() {
+            return Err(Error::SessionAlreadyActive);
+        } else {
+            let slot = Arc::new(());
+            self.immersive = Arc::downgrade(&slot);
+            Some(slot)
+        };
+        let mut unsupported = None;
+        let mut candidates: Vec<usize> = (0..self.discoveries.len())
+            .filter(|index| {
+                let discovery = &self.discoveries[*index].1;
+                if !discovery.supports_session(mode) {
+                    return false;
+                }
+                if init.required_features.is_empty() {
+                    return true;
+                }
+                let features = discovery.device_info().features;
+                match init
+                    .required_features
+                    .iter()
+                    .find(|feature| !features.contains(feature))
+                {
+                    Some(feature) => {
+                        unsupported.get_or_insert_with(|| feature.clone());
+                        false
+                    }
+                    None => true,
+                }
+            })
+            .collect();
+        let discoveries = &self.discoveries;
+        if let Some(ref mut policy) = self.policy {
+            let devices: Vec<_> = candidates
+                .iter()
+                .map(|index| &discoveries[*index])
+                .map(|(id, discovery)| (*id, discovery.device_info()))
+                .collect();
+            if let Some(chosen) = policy(mode, &devices) {
+                if let Some(position) = devices.iter().position(|(id, _)| *id == chosen) {
+                    let index = candidates.remove(position);
+                    candidates.insert(0, index);
+                }
+            }
+        }
+        let mut error = None;
+        for index in candidates {
+            let discovery = &mut self.discoveries[index].1;
+            let webgl = self.webgl.as_ref().map(|webgl| &**webgl);
+            let pixels = self.pixels.as_ref().map(|pixels| &**pixels);
+            let sessions = &mut self.sessions;
+            let spawned = &mut self.spawned;
+            let xr = SessionBuilder::new(webgl, pixels, sessions, spawned, slot.clone());
+            match discovery.request_session(mode, xr) {
+                Ok(mut session) => {
+                    let features = discovery.device_info().features;
+                    session.granted_features = granted
+                        .into_iter()
+                        .filter(|feature| features.contains(feature))
+                        .collect();
+                    return Ok(session);
+                }
+                Err(err) => error = error.or(Some(err)),
+            }
+        }
+        let unsupported = unsupported.map(Error::UnsupportedFeature);
+        Err(error
+            .or(unsupported)
+            .unwrap_or(Error::UnsupportedMode(mode)))
     }
 
     fn simulate_device_connection(
         &mut self,
         init: MockDeviceInit,
     ) -> Result<Sender<MockDeviceMsg>, Error> {
+        if self.shut_down {
+            return Err(Error::CommunicationError);
+        }
         for mock in &mut self.mocks {
             let (sender, receiver) = crate::channel().or(Err(Error::CommunicationError))?;
-            if let Ok(discovery) = mock.simulate_device_connection(init.clone(), receiver) {
-                self.discoveries.insert(0, discovery);
+            if let Ok(mut discovery) = mock.simulate_device_connection(init.clone(), receiver) {
+                let id = self.next_device_id();
+                discovery.set_device_notifier(self.device_notifier(id));
+                self.discoveries.insert(0, (id, discovery));
+                self.mock_senders.push((id, sender.clone()));
+                self.device_changed(DeviceChange::Connected(id));
                 return Ok(sender);
             }
         }
@@ -208,9 +679,29 @@ impl MainThreadRegistry {
     }
 }
 
+// The session received takes over ending the session, or it is ended here if it can't be sent.
+fn send_session(dest: &Sender<Result<Session, Error>>, result: Result<Session, Error>) {
+    match result {
+        #[cfg(feature = "ipc")]
+        Ok(session) => {
+            let quitter = session.quitter();
+            if dest.send(Ok(session.into_transferable())).is_err() {
+                quitter.quit(EndReason::UserRequested);
+            }
+        }
+        result => {
+            let _ = dest.send(result);
+        }
+    }
+}
+
+#[allow(clippy::large_enum_variant)]
 #[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
 enum RegistryMsg {
-    RequestSession(SessionMode, Sender<Result<Session, Error>>),
+    RequestSession(SessionMode, SessionInit, Sender<Result<Session, Error>>),
+    PermissionResponse(u32, Option<Vec<String>>),
     SupportsSession(SessionMode, Sender<Result<(), Error>>),
     SimulateDeviceConnection(MockDeviceInit, Sender<Result<Sender<MockDeviceMsg>, Error>>),
+    SubscribeDeviceChanges(Sender<DeviceChange>),
+    DeviceChanged(DeviceChange),
 }
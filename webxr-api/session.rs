@@ -2,45 +2,83 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
 
+use crate::DeadlineReceiver;
 use crate::Device;
+use crate::EndReason;
 use crate::Error;
 use crate::Event;
 use crate::Floor;
 use crate::Frame;
+#[cfg(feature = "shmem")]
+use crate::FrameRingHandle;
+#[cfg(feature = "shmem")]
+use crate::FrameRingWriter;
 use crate::InputSource;
 use crate::Native;
-use crate::Receiver;
+use crate::PixelBufferApi;
+use crate::PixelBufferId;
+#[cfg(feature = "async")]
+use crate::RecvStream;
 use crate::Sender;
+#[cfg(feature = "shmem")]
+use crate::SharedFrame;
 use crate::Viewport;
 use crate::Views;
 use crate::WebGLContextId;
 use crate::WebGLExternalImageApi;
 use crate::WebGLTextureId;
 
+#[cfg(feature = "async")]
+use crate::stream::ReplyStream;
+
 use euclid::default::Size2D as UntypedSize2D;
 use euclid::RigidTransform3D;
 use euclid::Size2D;
 
 use gleam::gl::GLsizei;
 
+#[cfg(feature = "async")]
+use futures::Stream;
+
+#[cfg(feature = "async")]
+use std::pin::Pin;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+#[cfg(feature = "async")]
+use std::task::Context;
+#[cfg(feature = "async")]
+use std::task::Poll;
 use std::thread;
+use std::thread::JoinHandle;
 use std::time::Duration;
 
-#[cfg(feature = "ipc")]
+#[cfg(feature = "serialize")]
 use serde::{Deserialize, Serialize};
+#[cfg(feature = "ipc")]
+use serde::{Deserializer, Serializer};
 
 // How long to wait for an rAF.
 static TIMEOUT: Duration = Duration::from_millis(5);
 
 /// https://www.w3.org/TR/webxr/#xrsessionmode-enum
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
-#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 pub enum SessionMode {
     Inline,
     ImmersiveVR,
     ImmersiveAR,
 }
 
+/// The features content asked for when requesting a session.
+/// https://immersive-web.github.io/webxr/#dictdef-xrsessioninit
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct SessionInit {
+    pub required_features: Vec<String>,
+    pub optional_features: Vec<String>,
+}
+
 /// https://www.w3.org/TR/hr-time/#dom-domhighrestimestamp
 pub type HighResTimeStamp = f64;
 
@@ -48,10 +86,16 @@ pub type HighResTimeStamp = f64;
 #[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
 enum SessionMsg {
     SetTexture(WebGLContextId, WebGLTextureId, UntypedSize2D<GLsizei>),
+    SetTextureArray(WebGLContextId, WebGLTextureId, UntypedSize2D<GLsizei>),
+    SetPixelBuffer(PixelBufferId),
     SetEventDest(Sender<Event>),
     RequestAnimationFrame(Sender<(HighResTimeStamp, Frame)>),
+    #[cfg(feature = "shmem")]
+    RequestSharedAnimationFrame(Sender<(HighResTimeStamp, SharedFrame)>),
     RenderAnimationFrame,
-    Quit,
+    SetFoveationLevel(f32),
+    /// End the session, and acknowledge once the device has been dropped
+    Quit(EndReason, Option<Sender<()>>),
 }
 
 #[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
@@ -61,8 +105,8 @@ pub struct Quitter {
 }
 
 impl Quitter {
-    pub fn quit(&self) {
-        let _ = self.sender.send(SessionMsg::Quit);
+    pub fn quit(&self, reason: EndReason) {
+        let _ = self.sender.send(SessionMsg::Quit(reason, None));
     }
 }
 
@@ -76,11 +120,40 @@ pub struct Session {
     resolution: Size2D<i32, Viewport>,
     sender: Sender<SessionMsg>,
     initial_inputs: Vec<InputSource>,
+    supports_foveation: bool,
+    supports_multiview: bool,
+    supports_pixel_buffers: bool,
+    pub(crate) granted_features: Vec<String>,
+    #[cfg(feature = "shmem")]
+    frame_ring: Option<FrameRingHandle>,
+    // Whether dropping this session ends it.
+    #[cfg_attr(
+        feature = "ipc",
+        serde(
+            serialize_with = "serialize_ownership",
+            deserialize_with = "deserialize_ownership"
+        )
+    )]
+    ownership: Ownership,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Ownership {
+    Owned,
+    // Being sent over IPC, after which the copy received owns the session.
+    #[cfg(feature = "ipc")]
+    Transferring,
+    Disowned,
 }
 
 impl Session {
+    /// The features requested by content which the session was granted.
+    pub fn granted_features(&self) -> &[String] {
+        &self.granted_features
+    }
+
     pub fn floor_transform(&self) -> RigidTransform3D<f32, Native, Floor> {
-        self.floor_transform.clone()
+        self.floor_transform
     }
 
     pub fn initial_inputs(&self) -> &[InputSource] {
@@ -95,6 +168,18 @@ impl Session {
         self.resolution
     }
 
+    /// Whether the device honours the foveation level.
+    /// https://immersive-web.github.io/webxr/#dom-xrwebgllayer-fixedfoveation
+    pub fn supports_foveation(&self) -> bool {
+        self.supports_foveation
+    }
+
+    /// Sets the fixed foveation level, from 0.0 (none) to 1.0 (maximum).
+    /// Levels outside that range are clamped, and levels which aren't finite turn foveation off.
+    pub fn set_foveation_level(&mut self, level: f32) {
+        let _ = self.sender.send(SessionMsg::SetFoveationLevel(level));
+    }
+
     pub fn set_texture(
         &mut self,
         ctxt: WebGLContextId,
@@ -104,10 +189,77 @@ impl Session {
         let _ = self.sender.send(SessionMsg::SetTexture(ctxt, txt, size));
     }
 
+    /// Whether the device accepts a texture array with one layer per view.
+    pub fn supports_multiview(&self) -> bool {
+        self.supports_multiview
+    }
+
+    /// Set a 2D texture array to render from, with one layer of the given size per view.
+    /// This is ignored if the device does not support multiview.
+    pub fn set_texture_array(
+        &mut self,
+        ctxt: WebGLContextId,
+        txt: WebGLTextureId,
+        size: UntypedSize2D<GLsizei>,
+    ) {
+        let _ = self
+            .sender
+            .send(SessionMsg::SetTextureArray(ctxt, txt, size));
+    }
+
+    /// Whether the device can display pixel buffers, for content rendered without GL.
+    pub fn supports_pixel_buffers(&self) -> bool {
+        self.supports_pixel_buffers
+    }
+
+    /// Set a pixel buffer to render from, instead of a texture.
+    /// This is ignored if the device does not support pixel buffers.
+    pub fn set_pixel_buffer(&mut self, id: PixelBufferId) {
+        let _ = self.sender.send(SessionMsg::SetPixelBuffer(id));
+    }
+
     pub fn request_animation_frame(&mut self, dest: Sender<(HighResTimeStamp, Frame)>) {
         let _ = self.sender.send(SessionMsg::RequestAnimationFrame(dest));
     }
 
+    /// The shared-memory ring that frames requested with
+    /// `request_shared_animation_frame` are written to, if it could be created.
+    #[cfg(feature = "shmem")]
+    pub fn frame_ring(&self) -> Option<FrameRingHandle> {
+        self.frame_ring.clone()
+    }
+
+    /// Like `request_animation_frame`, but the frame is written to the frame ring,
+    /// and only its location is sent through the channel.
+    #[cfg(feature = "shmem")]
+    pub fn request_shared_animation_frame(
+        &mut self,
+        dest: Sender<(HighResTimeStamp, SharedFrame)>,
+    ) {
+        let _ = self
+            .sender
+            .send(SessionMsg::RequestSharedAnimationFrame(dest));
+    }
+
+    /// A stream of animation frames. The next frame is requested when the stream is polled
+    /// after yielding a frame, so content should render each frame before polling again.
+    /// The stream ends when the session does.
+    #[cfg(feature = "async")]
+    pub fn frames(&self) -> FrameStream {
+        FrameStream {
+            sender: self.sender.clone(),
+            replies: ReplyStream::new(),
+        }
+    }
+
+    /// A stream of the session's events. This replaces any previous event destination.
+    #[cfg(feature = "async")]
+    pub fn events(&mut self) -> Result<RecvStream<Event>, Error> {
+        let (dest, receiver) = crate::channel().or(Err(Error::CommunicationError))?;
+        self.set_event_dest(dest);
+        Ok(crate::stream::recv_stream(receiver))
+    }
+
     pub fn set_event_dest(&mut self, dest: Sender<Event>) {
         let _ = self.sender.send(SessionMsg::SetEventDest(dest));
     }
@@ -117,41 +269,164 @@ impl Session {
     }
 
     pub fn end_session(&mut self) {
-        let _ = self.sender.send(SessionMsg::Quit);
+        self.ownership = Ownership::Disowned;
+        let _ = self
+            .sender
+            .send(SessionMsg::Quit(EndReason::UserRequested, None));
+    }
+
+    /// End the session, and send an acknowledgement once the session thread
+    /// has dropped the device, releasing its GL resources.
+    pub fn end_session_with_ack(&mut self, ack: Sender<()>) {
+        self.ownership = Ownership::Disowned;
+        let _ = self
+            .sender
+            .send(SessionMsg::Quit(EndReason::UserRequested, Some(ack)));
+    }
+
+    /// Hand the session over to whoever it is sent to over IPC, so that dropping
+    /// this copy doesn't end it, but dropping the copy received does.
+    /// Call this just before sending the session; copies sent without it don't own it.
+    #[cfg(feature = "ipc")]
+    pub fn into_transferable(mut self) -> Session {
+        if self.ownership == Ownership::Owned {
+            self.ownership = Ownership::Transferring;
+        }
+        self
+    }
+
+    pub(crate) fn quitter(&self) -> Quitter {
+        Quitter {
+            sender: self.sender.clone(),
+        }
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        if self.ownership == Ownership::Owned {
+            let _ = self
+                .sender
+                .send(SessionMsg::Quit(EndReason::UserRequested, None));
+        }
+    }
+}
+
+#[cfg(feature = "ipc")]
+fn serialize_ownership<S: Serializer>(
+    ownership: &Ownership,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_bool(*ownership == Ownership::Transferring)
+}
+
+#[cfg(feature = "ipc")]
+fn deserialize_ownership<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Ownership, D::Error> {
+    match bool::deserialize(deserializer)? {
+        true => Ok(Ownership::Owned),
+        false => Ok(Ownership::Disowned),
+    }
+}
+
+/// A stream of animation frames for async content, from `Session::frames`.
+#[cfg(feature = "async")]
+pub struct FrameStream {
+    sender: Sender<SessionMsg>,
+    // Each request has its own reply channel, which is closed
+    // without a reply if the session ends before answering.
+    replies: ReplyStream<(HighResTimeStamp, Frame)>,
+}
+
+#[cfg(feature = "async")]
+impl Stream for FrameStream {
+    type Item = (HighResTimeStamp, Frame);
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        if !self.replies.waiting() {
+            let (dest, receiver) = match crate::channel() {
+                Ok(channel) => channel,
+                Err(_) => return Poll::Ready(None),
+            };
+            if self
+                .sender
+                .send(SessionMsg::RequestAnimationFrame(dest))
+                .is_err()
+            {
+                return Poll::Ready(None);
+            }
+            self.replies.expect(receiver);
+        }
+        Pin::new(&mut self.replies).poll_next(cx)
     }
 }
 
 /// For devices that want to do their own thread management, the `SessionThread` type is exposed.
 pub struct SessionThread<D> {
-    receiver: Receiver<SessionMsg>,
+    receiver: DeadlineReceiver<SessionMsg>,
     sender: Sender<SessionMsg>,
-    webgl: Box<dyn WebGLExternalImageApi>,
+    webgl: Option<Box<dyn WebGLExternalImageApi>>,
+    pixels: Option<Box<dyn PixelBufferApi>>,
     texture: Option<(WebGLContextId, WebGLTextureId, UntypedSize2D<GLsizei>)>,
+    multiview: bool,
+    pixel_buffer: Option<PixelBufferId>,
     timestamp: HighResTimeStamp,
     running: bool,
     device: D,
+    // Held while the session is running, so the registry knows that an immersive session is active.
+    slot: Option<Arc<()>>,
+    #[cfg(feature = "shmem")]
+    frame_ring: Option<FrameRingWriter>,
+    // Dropped after the device, since fields are dropped in order.
+    ack: EndAck,
+}
+
+// Acknowledges the end of a session when it is dropped.
+struct EndAck(Option<Sender<()>>);
+
+impl Drop for EndAck {
+    fn drop(&mut self) {
+        if let Some(ref ack) = self.0 {
+            let _ = ack.send(());
+        }
+    }
 }
 
 impl<D: Device> SessionThread<D> {
     pub fn new(
         mut device: D,
-        webgl: Box<dyn WebGLExternalImageApi>,
+        webgl: Option<Box<dyn WebGLExternalImageApi>>,
+        pixels: Option<Box<dyn PixelBufferApi>>,
     ) -> Result<SessionThread<D>, Error> {
+        if webgl.is_none() && !device.supports_pixel_buffers() {
+            return Err(Error::UnsupportedFeature("pixel buffers".into()));
+        }
         let (sender, receiver) = crate::channel().or(Err(Error::CommunicationError))?;
+        let receiver = DeadlineReceiver::new(receiver);
         device.set_quitter(Quitter {
             sender: sender.clone(),
         });
         let timestamp = 0.0;
         let texture = None;
+        let multiview = false;
+        let pixel_buffer = None;
         let running = true;
         Ok(SessionThread {
             sender,
             receiver,
             device,
             webgl,
+            pixels,
             texture,
+            multiview,
+            pixel_buffer,
             timestamp,
             running,
+            slot: None,
+            #[cfg(feature = "shmem")]
+            frame_ring: FrameRingWriter::new().ok(),
+            ack: EndAck(None),
         })
     }
 
@@ -161,23 +436,29 @@ impl<D: Device> SessionThread<D> {
         let resolution = self.device.recommended_framebuffer_resolution();
         let sender = self.sender.clone();
         let initial_inputs = self.device.initial_inputs();
+        let supports_foveation = self.device.supports_foveation();
+        let supports_multiview = self.device.supports_multiview();
+        let supports_pixel_buffers = self.device.supports_pixel_buffers();
         Session {
             floor_transform,
             views,
             resolution,
             sender,
             initial_inputs,
+            supports_foveation,
+            supports_multiview,
+            supports_pixel_buffers,
+            granted_features: vec![],
+            #[cfg(feature = "shmem")]
+            frame_ring: self.frame_ring.as_ref().map(|ring| ring.handle()),
+            ownership: Ownership::Owned,
         }
     }
 
     pub fn run(&mut self) {
-        loop {
-            if let Ok(msg) = self.receiver.recv() {
-                if !self.handle_msg(msg) {
-                    self.running = false;
-                    break;
-                }
-            } else {
+        while let Ok(msg) = self.receiver.recv() {
+            if !self.handle_msg(msg) {
+                self.running = false;
                 break;
             }
         }
@@ -187,6 +468,21 @@ impl<D: Device> SessionThread<D> {
         match msg {
             SessionMsg::SetTexture(ctxt, txt, size) => {
                 self.texture = Some((ctxt, txt, size));
+                self.multiview = false;
+                self.pixel_buffer = None;
+            }
+            SessionMsg::SetTextureArray(ctxt, txt, size) => {
+                if self.device.supports_multiview() {
+                    self.texture = Some((ctxt, txt, size));
+                    self.multiview = true;
+                    self.pixel_buffer = None;
+                }
+            }
+            SessionMsg::SetPixelBuffer(id) => {
+                if self.device.supports_pixel_buffers() {
+                    self.texture = None;
+                    self.pixel_buffer = Some(id);
+                }
             }
             SessionMsg::SetEventDest(dest) => {
                 self.device.set_event_dest(dest);
@@ -196,16 +492,48 @@ impl<D: Device> SessionThread<D> {
                 let frame = self.device.wait_for_animation_frame();
                 let _ = dest.send((timestamp, frame));
             }
+            #[cfg(feature = "shmem")]
+            SessionMsg::RequestSharedAnimationFrame(dest) => {
+                let timestamp = self.timestamp;
+                let frame = self.device.wait_for_animation_frame();
+                let frame = match self.frame_ring {
+                    Some(ref mut ring) => ring.write(&frame),
+                    None => SharedFrame::Inline(frame),
+                };
+                let _ = dest.send((timestamp, frame));
+            }
             SessionMsg::RenderAnimationFrame => {
                 self.timestamp += 1.0;
-                if let Some((ctxt, txt, size)) = self.texture {
-                    let sync = self.webgl.lock(ctxt);
-                    self.device.render_animation_frame(txt, size, sync);
-                    self.webgl.unlock(ctxt);
+                if let (Some((ctxt, txt, size)), Some(webgl)) = (self.texture, &self.webgl) {
+                    let sync = webgl.lock(ctxt);
+                    if self.multiview {
+                        self.device
+                            .render_multiview_animation_frame(txt, size, sync);
+                    } else {
+                        self.device.render_animation_frame(txt, size, sync);
+                    }
+                    webgl.unlock(ctxt);
                 }
+                if let (Some(id), Some(pixels)) = (self.pixel_buffer, &self.pixels) {
+                    if let Some(buffer) = pixels.lock(id) {
+                        self.device.render_pixel_frame(&buffer);
+                    }
+                    pixels.unlock(id);
+                }
+            }
+            SessionMsg::SetFoveationLevel(level) => {
+                let level = if !level.is_finite() || level < 0.0 {
+                    0.0
+                } else if level > 1.0 {
+                    1.0
+                } else {
+                    level
+                };
+                self.device.set_foveation_level(level);
             }
-            SessionMsg::Quit => {
-                self.device.quit();
+            SessionMsg::Quit(reason, ack) => {
+                self.device.quit(reason);
+                self.ack = EndAck(ack);
                 return false;
             }
         }
@@ -217,14 +545,39 @@ impl<D: Device> SessionThread<D> {
 pub trait MainThreadSession: 'static {
     fn run_one_frame(&mut self);
     fn running(&self) -> bool;
+    fn quit(&mut self, reason: EndReason);
+}
+
+/// A session thread started by `SessionBuilder::spawn`, which the registry joins when it shuts down.
+pub(crate) struct SpawnedSession {
+    quitter: Quitter,
+    handle: JoinHandle<()>,
+    // Set once the thread is about to finish.
+    finished: Arc<AtomicBool>,
+}
+
+impl SpawnedSession {
+    pub(crate) fn quit(&self, reason: EndReason) {
+        self.quitter.quit(reason);
+    }
+
+    pub(crate) fn join(self) {
+        let _ = self.handle.join();
+    }
+
+    pub(crate) fn finished(&self) -> bool {
+        self.finished.load(Ordering::Acquire)
+    }
 }
 
 impl<D: Device> MainThreadSession for SessionThread<D> {
     fn run_one_frame(&mut self) {
         let timestamp = self.timestamp;
         while timestamp == self.timestamp && self.running {
-            if let Ok(msg) = crate::recv_timeout(&self.receiver, TIMEOUT) {
-                self.handle_msg(msg);
+            if let Ok(msg) = self.receiver.recv_timeout(TIMEOUT) {
+                if !self.handle_msg(msg) {
+                    self.running = false;
+                }
             } else {
                 break;
             }
@@ -234,20 +587,60 @@ impl<D: Device> MainThreadSession for SessionThread<D> {
     fn running(&self) -> bool {
         self.running
     }
+
+    fn quit(&mut self, reason: EndReason) {
+        if self.running {
+            self.handle_msg(SessionMsg::Quit(reason, None));
+            self.running = false;
+        }
+    }
 }
 
+/// A function which wraps the device of a new session, for example to record it.
+pub type DeviceWrapper = Box<dyn FnOnce(Box<dyn Device>) -> Result<Box<dyn Device>, Error> + Send>;
+
 /// A type for building XR sessions
 pub struct SessionBuilder<'a> {
-    webgl: &'a dyn WebGLExternalImageApi,
+    webgl: Option<&'a dyn WebGLExternalImageApi>,
+    pixels: Option<&'a dyn PixelBufferApi>,
     sessions: &'a mut Vec<Box<dyn MainThreadSession>>,
+    spawned: &'a mut Vec<SpawnedSession>,
+    wrapper: Option<DeviceWrapper>,
+    slot: Option<Arc<()>>,
 }
 
 impl<'a> SessionBuilder<'a> {
     pub(crate) fn new(
-        webgl: &'a dyn WebGLExternalImageApi,
+        webgl: Option<&'a dyn WebGLExternalImageApi>,
+        pixels: Option<&'a dyn PixelBufferApi>,
         sessions: &'a mut Vec<Box<dyn MainThreadSession>>,
+        spawned: &'a mut Vec<SpawnedSession>,
+        slot: Option<Arc<()>>,
     ) -> SessionBuilder<'a> {
-        SessionBuilder { webgl, sessions }
+        SessionBuilder {
+            webgl,
+            pixels,
+            sessions,
+            spawned,
+            wrapper: None,
+            slot,
+        }
+    }
+
+    /// Wrap the device built for this session. Wrappers added later are
+    /// closer to the device, so the first wrapper sees what content sees.
+    pub fn wrap_device<W>(self, wrapper: W) -> SessionBuilder<'a>
+    where
+        W: 'static + FnOnce(Box<dyn Device>) -> Result<Box<dyn Device>, Error> + Send,
+    {
+        let wrapper: DeviceWrapper = match self.wrapper {
+            Some(outer) => Box::new(move |device| wrapper(device).and_then(outer)),
+            None => Box::new(wrapper),
+        };
+        SessionBuilder {
+            wrapper: Some(wrapper),
+            ..self
+        }
     }
 
     /// For devices which are happy to hand over thread management to webxr.
@@ -257,20 +650,39 @@ impl<'a> SessionBuilder<'a> {
         D: Device,
     {
         let (acks, ackr) = crate::channel().or(Err(Error::CommunicationError))?;
-        let webgl = self.webgl.clone_box();
-        thread::spawn(move || {
-            match factory().and_then(|device| SessionThread::new(device, webgl)) {
+        let webgl = self.webgl.map(|webgl| webgl.clone_box());
+        let pixels = self.pixels.map(|pixels| pixels.clone_box());
+        let wrapper = self.wrapper;
+        let slot = self.slot;
+        let finished = Arc::new(AtomicBool::new(false));
+        let finished_ = finished.clone();
+        let handle = thread::spawn(move || {
+            match factory()
+                .and_then(|device| wrap_device(device, wrapper))
+                .and_then(|device| SessionThread::new(device, webgl, pixels))
+            {
                 Ok(mut thread) => {
+                    thread.slot = slot;
                     let session = thread.new_session();
-                    let _ = acks.send(Ok(session));
-                    thread.run();
+                    #[cfg(feature = "ipc")]
+                    let session = session.into_transferable();
+                    if acks.send(Ok(session)).is_ok() {
+                        thread.run();
+                    }
                 }
                 Err(err) => {
                     let _ = acks.send(Err(err));
                 }
             }
+            finished_.store(true, Ordering::Release);
+        });
+        let session = ackr.recv().unwrap_or(Err(Error::CommunicationError))?;
+        self.spawned.push(SpawnedSession {
+            quitter: session.quitter(),
+            handle,
+            finished,
         });
-        ackr.recv().unwrap_or(Err(Error::CommunicationError))
+        Ok(session)
     }
 
     /// For devices that need to run on the main thread.
@@ -279,11 +691,23 @@ impl<'a> SessionBuilder<'a> {
         F: 'static + FnOnce() -> Result<D, Error>,
         D: Device,
     {
-        let device = factory()?;
-        let webgl = self.webgl.clone_box();
-        let mut session_thread = SessionThread::new(device, webgl)?;
+        let device = wrap_device(factory()?, self.wrapper)?;
+        let webgl = self.webgl.map(|webgl| webgl.clone_box());
+        let pixels = self.pixels.map(|pixels| pixels.clone_box());
+        let mut session_thread = SessionThread::new(device, webgl, pixels)?;
+        session_thread.slot = self.slot;
         let session = session_thread.new_session();
         self.sessions.push(Box::new(session_thread));
         Ok(session)
     }
 }
+
+fn wrap_device<D: Device>(
+    device: D,
+    wrapper: Option<DeviceWrapper>,
+) -> Result<Box<dyn Device>, Error> {
+    match wrapper {
+        Some(wrapper) => wrapper(Box::new(device)),
+        None => Ok(Box::new(device)),
+    }
+}
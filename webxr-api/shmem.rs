@@ -0,0 +1,367 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! A shared-memory ring buffer for per-frame data, so that frames are not serialized
+//! through an IPC channel. The session thread writes each frame to the next slot,
+//! and only sends content a small `SharedFrame` saying which slot to read.
+//!
+//! The ring is a POSIX shared memory object, which the session thread creates and maps
+//! writable, and content opens by name and maps. It is removed when the writer is dropped.
+//! There is no ring on platforms without POSIX shared memory.
+//!
+//! Each slot is guarded by a sequence number, which is odd while the slot is
+//! being written. If content falls so far behind that a slot is overwritten
+//! before it is read, reading it fails, and content should request another frame.
+//! The ring is only ever accessed through atomics, since the other side may be
+//! writing to it at the same time.
+
+use crate::Frame;
+
+use serde::{Deserialize, Serialize};
+
+use std::io;
+#[cfg(unix)]
+use std::os::raw::c_int;
+#[cfg(unix)]
+use std::os::raw::c_uint;
+use std::process;
+use std::slice;
+use std::sync::atomic::fence;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+
+// Frames are small, so a few pages are plenty. Frames which
+// don't fit in a slot are sent through the channel instead.
+const SLOT_COUNT: usize = 4;
+const SLOT_SIZE: usize = 4096;
+
+// Each slot starts with its sequence number and the length of its data,
+// and the ring is accessed a 64-bit word at a time.
+const WORD_SIZE: usize = 8;
+const SLOT_HEADER_WORDS: usize = 2;
+const SLOT_WORDS: usize = SLOT_HEADER_WORDS + SLOT_SIZE / WORD_SIZE;
+const RING_SIZE: usize = SLOT_COUNT * SLOT_WORDS * WORD_SIZE;
+
+// Used to give each ring in this process a different name.
+static RING_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// The frame for an animation frame request, either in the ring or inline.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum SharedFrame {
+    Slot { index: usize, seq: u64 },
+    Inline(Frame),
+}
+
+/// A frame ring, which can be sent to content.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FrameRingHandle {
+    name: String,
+}
+
+pub struct FrameRingWriter {
+    mapping: Mapping,
+    name: String,
+    count: u64,
+    buffer: Vec<u8>,
+}
+
+pub struct FrameRingReader {
+    mapping: Mapping,
+}
+
+impl FrameRingWriter {
+    pub fn new() -> io::Result<FrameRingWriter> {
+        let count = RING_COUNT.fetch_add(1, Ordering::Relaxed);
+        let name = format!("/webxr-{}-{}", process::id(), count);
+        let mapping = Mapping::create(&name)?;
+        Ok(FrameRingWriter {
+            mapping,
+            name,
+            count: 0,
+            buffer: Vec::with_capacity(SLOT_SIZE),
+        })
+    }
+
+    pub fn handle(&self) -> FrameRingHandle {
+        FrameRingHandle {
+            name: self.name.clone(),
+        }
+    }
+
+    pub fn write(&mut self, frame: &Frame) -> SharedFrame {
+        self.buffer.clear();
+        if bincode::serialize_into(&mut self.buffer, frame).is_err()
+            || self.buffer.len() > SLOT_SIZE
+        {
+            return SharedFrame::Inline(frame.clone());
+        }
+        self.count += 1;
+        let index = (self.count % SLOT_COUNT as u64) as usize;
+        let seq = self.count * 2;
+        let slot = slot(self.mapping.words(), index);
+        slot[0].store(seq - 1, Ordering::Relaxed);
+        fence(Ordering::Release);
+        slot[1].store(self.buffer.len() as u64, Ordering::Relaxed);
+        let data = &slot[SLOT_HEADER_WORDS..];
+        for (word, bytes) in data.iter().zip(self.buffer.chunks(WORD_SIZE)) {
+            let mut padded = [0; WORD_SIZE];
+            padded[..bytes.len()].copy_from_slice(bytes);
+            word.store(u64::from_ne_bytes(padded), Ordering::Relaxed);
+        }
+        slot[0].store(seq, Ordering::Release);
+        SharedFrame::Slot { index, seq }
+    }
+}
+
+impl Drop for FrameRingWriter {
+    fn drop(&mut self) {
+        Mapping::unlink(&self.name);
+    }
+}
+
+impl FrameRingHandle {
+    pub fn open(&self) -> io::Result<FrameRingReader> {
+        let mapping = Mapping::open(&self.name)?;
+        Ok(FrameRingReader { mapping })
+    }
+}
+
+impl FrameRingReader {
+    /// The frame, or `None` if its slot has since been overwritten.
+    pub fn read(&self, frame: SharedFrame) -> Option<Frame> {
+        let (index, seq) = match frame {
+            SharedFrame::Slot { index, seq } if index < SLOT_COUNT => (index, seq),
+            SharedFrame::Slot { .. } => return None,
+            SharedFrame::Inline(frame) => return Some(frame),
+        };
+        let slot = slot(self.mapping.words(), index);
+        if slot[0].load(Ordering::Acquire) != seq {
+            return None;
+        }
+        // If the slot is overwritten while we copy it, we copy garbage, but it is
+        // bounded by the slot, and thrown away before it is deserialized.
+        let len = (slot[1].load(Ordering::Relaxed) as usize).min(SLOT_SIZE);
+        let mut buffer = Vec::with_capacity(len + WORD_SIZE);
+        for word in &slot[SLOT_HEADER_WORDS..] {
+            if buffer.len() >= len {
+                break;
+            }
+            buffer.extend_from_slice(&word.load(Ordering::Relaxed).to_ne_bytes());
+        }
+        fence(Ordering::Acquire);
+        if slot[0].load(Ordering::Relaxed) != seq {
+            return None;
+        }
+        bincode::deserialize(&buffer[..len]).ok()
+    }
+}
+
+fn slot(words: &[AtomicU64], index: usize) -> &[AtomicU64] {
+    &words[index * SLOT_WORDS..(index + 1) * SLOT_WORDS]
+}
+
+// A mapping of the whole ring.
+struct Mapping {
+    ptr: *mut u8,
+}
+
+// The mapping is only accessed through atomics.
+unsafe impl Send for Mapping {}
+unsafe impl Sync for Mapping {}
+
+impl Mapping {
+    fn words(&self) -> &[AtomicU64] {
+        // The mapping is page-aligned, and lives as long as we do.
+        unsafe { slice::from_raw_parts(self.ptr as *const AtomicU64, RING_SIZE / WORD_SIZE) }
+    }
+}
+
+#[cfg(unix)]
+impl Mapping {
+    fn create(name: &str) -> io::Result<Mapping> {
+        let flags = libc::O_RDWR | libc::O_CREAT | libc::O_EXCL;
+        let fd = shm_open(name, flags, 0o600)?;
+        if unsafe { libc::ftruncate(fd, RING_SIZE as libc::off_t) } != 0 {
+            let err = io::Error::last_os_error();
+            unsafe { libc::close(fd) };
+            Mapping::unlink(name);
+            return Err(err);
+        }
+        let mapping = Mapping::map(fd);
+        if mapping.is_err() {
+            Mapping::unlink(name);
+        }
+        mapping
+    }
+
+    fn open(name: &str) -> io::Result<Mapping> {
+        // Atomic loads may be compare-exchanges on some targets,
+        // so the reader maps the ring writable too.
+        let fd = shm_open(name, libc::O_RDWR, 0)?;
+        let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+        if unsafe { libc::fstat(fd, &mut stat) } != 0 {
+            let err = io::Error::last_os_error();
+            unsafe { libc::close(fd) };
+            return Err(err);
+        }
+        // Reading past the end of the object would crash.
+        if (stat.st_size as usize) < RING_SIZE {
+            unsafe { libc::close(fd) };
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Frame ring is too small",
+            ));
+        }
+        Mapping::map(fd)
+    }
+
+    fn map(fd: c_int) -> io::Result<Mapping> {
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                RING_SIZE,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                fd,
+                0,
+            )
+        };
+        let err = io::Error::last_os_error();
+        unsafe { libc::close(fd) };
+        if ptr == libc::MAP_FAILED {
+            return Err(err);
+        }
+        Ok(Mapping {
+            ptr: ptr as *mut u8,
+        })
+    }
+
+    fn unlink(name: &str) {
+        if let Ok(name) = std::ffi::CString::new(name) {
+            unsafe { libc::shm_unlink(name.as_ptr()) };
+        }
+    }
+}
+
+#[cfg(unix)]
+fn shm_open(name: &str, flags: c_int, mode: libc::mode_t) -> io::Result<c_int> {
+    let name = std::ffi::CString::new(name)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Bad frame ring name"))?;
+    let fd = unsafe { libc::shm_open(name.as_ptr(), flags, mode as c_uint) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(fd)
+}
+
+#[cfg(unix)]
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.ptr as *mut libc::c_void, RING_SIZE) };
+    }
+}
+
+#[cfg(not(unix))]
+impl Mapping {
+    fn create(_: &str) -> io::Result<Mapping> {
+        Err(Mapping::unsupported())
+    }
+
+    fn open(_: &str) -> io::Result<Mapping> {
+        Err(Mapping::unsupported())
+    }
+
+    fn unlink(_: &str) {}
+
+    fn unsupported() -> io::Error {
+        io::Error::new(
+            io::ErrorKind::Other,
+            "There is no shared memory on this platform",
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FrameRingWriter;
+    use super::SharedFrame;
+    use super::SLOT_COUNT;
+
+    use crate::Frame;
+    use crate::InputFrame;
+    use crate::InputId;
+
+    use euclid::RigidTransform3D;
+    use euclid::Vector3D;
+
+    fn frame(x: f32) -> Frame {
+        let translation = Vector3D::new(x, 0.0, 0.0);
+        Frame {
+            transform: RigidTransform3D::from_translation(translation),
+            inputs: vec![],
+        }
+    }
+
+    fn x(frame: Option<Frame>) -> Option<f32> {
+        frame.map(|frame| frame.transform.translation.x)
+    }
+
+    #[test]
+    fn write_then_read() {
+        let mut writer = FrameRingWriter::new().unwrap();
+        let reader = writer.handle().open().unwrap();
+        let first = writer.write(&frame(1.0));
+        let second = writer.write(&frame(2.0));
+        assert_eq!(x(reader.read(second)), Some(2.0));
+        assert_eq!(x(reader.read(first.clone())), Some(1.0));
+        // Reading doesn't consume the slot.
+        assert_eq!(x(reader.read(first)), Some(1.0));
+    }
+
+    #[test]
+    fn handles_can_be_sent() {
+        let mut writer = FrameRingWriter::new().unwrap();
+        let (sender, receiver) = ipc_channel::ipc::channel().unwrap();
+        sender.send(writer.handle()).unwrap();
+        let reader = receiver.recv().unwrap().open().unwrap();
+        let frame = writer.write(&frame(1.0));
+        assert_eq!(x(reader.read(frame)), Some(1.0));
+    }
+
+    #[test]
+    fn overwritten_slots_are_not_read() {
+        let mut writer = FrameRingWriter::new().unwrap();
+        let reader = writer.handle().open().unwrap();
+        let first = writer.write(&frame(0.0));
+        let mut last = first.clone();
+        for i in 1..=SLOT_COUNT {
+            last = writer.write(&frame(i as f32));
+        }
+        // The first slot has been reused for the last frame.
+        match (&first, &last) {
+            (SharedFrame::Slot { index: a, .. }, SharedFrame::Slot { index: b, .. }) => {
+                assert_eq!(a, b)
+            }
+            _ => panic!("Frames should be in the ring"),
+        }
+        assert_eq!(x(reader.read(first)), None);
+        assert_eq!(x(reader.read(last)), Some(SLOT_COUNT as f32));
+    }
+
+    #[test]
+    fn large_frames_are_inline() {
+        let mut writer = FrameRingWriter::new().unwrap();
+        let mut large = frame(1.0);
+        let input = InputFrame {
+            id: InputId(0),
+            target_ray_origin: RigidTransform3D::identity(),
+        };
+        large.inputs = vec![input; 1000];
+        match writer.write(&large) {
+            SharedFrame::Inline(frame) => assert_eq!(frame.inputs.len(), 1000),
+            SharedFrame::Slot { .. } => panic!("Frame should be inline"),
+        }
+    }
+}
@@ -0,0 +1,222 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Futures and streams of the messages received on our channels, for async content.
+//!
+//! Channels are forwarded into futures channels by the IPC router,
+//! or without IPC, by a thread which blocks on the channel. Without IPC,
+//! streams of replies, such as `Session::frames`, share a thread for all their requests.
+
+use crate::Error;
+use crate::Receiver;
+
+use futures::channel::mpsc;
+use futures::channel::oneshot;
+use futures::Future;
+use futures::Stream;
+
+use std::pin::Pin;
+use std::task::Context;
+use std::task::Poll;
+
+/// A future for the first message received on a channel.
+pub struct RecvFuture<T> {
+    receiver: oneshot::Receiver<T>,
+}
+
+/// A stream of the messages received on a channel,
+/// which ends when every sender has been dropped.
+pub struct RecvStream<T> {
+    receiver: mpsc::UnboundedReceiver<T>,
+}
+
+/// A stream of the replies to a series of requests, each with its own reply channel.
+/// It ends when a channel is closed without a reply.
+#[cfg(feature = "ipc")]
+pub(crate) struct ReplyStream<T> {
+    pending: Option<RecvFuture<T>>,
+}
+
+#[cfg(not(feature = "ipc"))]
+pub(crate) struct ReplyStream<T> {
+    // The thread waits on each reply channel in turn, and exits when this is dropped.
+    requests: std::sync::mpsc::Sender<Receiver<T>>,
+    replies: mpsc::UnboundedReceiver<T>,
+    waiting: bool,
+}
+
+impl<T> Future for RecvFuture<T> {
+    type Output = Result<T, Error>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<T, Error>> {
+        Pin::new(&mut self.receiver)
+            .poll(cx)
+            .map(|result| result.or(Err(Error::CommunicationError)))
+    }
+}
+
+impl<T> Stream for RecvStream<T> {
+    type Item = T;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<T>> {
+        Pin::new(&mut self.receiver).poll_next(cx)
+    }
+}
+
+#[cfg(feature = "ipc")]
+impl<T> ReplyStream<T>
+where
+    T: 'static + Send + serde::Serialize + for<'a> serde::Deserialize<'a>,
+{
+    pub(crate) fn new() -> ReplyStream<T> {
+        ReplyStream { pending: None }
+    }
+
+    pub(crate) fn waiting(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    /// Wait for a reply on this channel next.
+    pub(crate) fn expect(&mut self, receiver: Receiver<T>) {
+        self.pending = Some(recv_future(receiver));
+    }
+}
+
+#[cfg(feature = "ipc")]
+impl<T> Stream for ReplyStream<T> {
+    type Item = T;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<T>> {
+        let poll = match self.pending {
+            Some(ref mut pending) => Pin::new(pending).poll(cx),
+            None => return Poll::Ready(None),
+        };
+        poll.map(|reply| {
+            self.pending = None;
+            reply.ok()
+        })
+    }
+}
+
+#[cfg(not(feature = "ipc"))]
+impl<T: 'static + Send> ReplyStream<T> {
+    pub(crate) fn new() -> ReplyStream<T> {
+        let (requests, request_receiver) = std::sync::mpsc::channel::<Receiver<T>>();
+        let (sender, replies) = mpsc::unbounded();
+        std::thread::spawn(move || {
+            for receiver in request_receiver {
+                let reply = match receiver.recv() {
+                    Ok(reply) => reply,
+                    Err(_) => break,
+                };
+                if sender.unbounded_send(reply).is_err() {
+                    break;
+                }
+            }
+        });
+        ReplyStream {
+            requests,
+            replies,
+            waiting: false,
+        }
+    }
+
+    pub(crate) fn waiting(&self) -> bool {
+        self.waiting
+    }
+
+    /// Wait for a reply on this channel next.
+    pub(crate) fn expect(&mut self, receiver: Receiver<T>) {
+        self.waiting = self.requests.send(receiver).is_ok();
+    }
+}
+
+#[cfg(not(feature = "ipc"))]
+impl<T> Stream for ReplyStream<T> {
+    type Item = T;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<T>> {
+        if !self.waiting {
+            return Poll::Ready(None);
+        }
+        let poll = Pin::new(&mut self.replies).poll_next(cx);
+        if poll.is_ready() {
+            self.waiting = false;
+        }
+        poll
+    }
+}
+
+#[cfg(feature = "ipc")]
+pub(crate) fn recv_future<T>(receiver: Receiver<T>) -> RecvFuture<T>
+where
+    T: 'static + Send + serde::Serialize + for<'a> serde::Deserialize<'a>,
+{
+    let (sender, future_receiver) = oneshot::channel();
+    let mut sender = Some(sender);
+    ipc_channel::router::ROUTER.add_route(
+        receiver.to_opaque(),
+        Box::new(move |msg| {
+            if let (Some(sender), Ok(msg)) = (sender.take(), msg.to()) {
+                let _ = sender.send(msg);
+            }
+        }),
+    );
+    RecvFuture {
+        receiver: future_receiver,
+    }
+}
+
+#[cfg(not(feature = "ipc"))]
+pub(crate) fn recv_future<T>(receiver: Receiver<T>) -> RecvFuture<T>
+where
+    T: 'static + Send,
+{
+    let (sender, future_receiver) = oneshot::channel();
+    std::thread::spawn(move || {
+        if let Ok(msg) = receiver.recv() {
+            let _ = sender.send(msg);
+        }
+    });
+    RecvFuture {
+        receiver: future_receiver,
+    }
+}
+
+#[cfg(feature = "ipc")]
+pub(crate) fn recv_stream<T>(receiver: Receiver<T>) -> RecvStream<T>
+where
+    T: 'static + Send + serde::Serialize + for<'a> serde::Deserialize<'a>,
+{
+    let (sender, stream_receiver) = mpsc::unbounded();
+    ipc_channel::router::ROUTER.add_route(
+        receiver.to_opaque(),
+        Box::new(move |msg| {
+            if let Ok(msg) = msg.to() {
+                let _ = sender.unbounded_send(msg);
+            }
+        }),
+    );
+    RecvStream {
+        receiver: stream_receiver,
+    }
+}
+
+#[cfg(not(feature = "ipc"))]
+pub(crate) fn recv_stream<T>(receiver: Receiver<T>) -> RecvStream<T>
+where
+    T: 'static + Send,
+{
+    let (sender, stream_receiver) = mpsc::unbounded();
+    std::thread::spawn(move || {
+        while let Ok(msg) = receiver.recv() {
+            if sender.unbounded_send(msg).is_err() {
+                break;
+            }
+        }
+    });
+    RecvStream {
+        receiver: stream_receiver,
+    }
+}
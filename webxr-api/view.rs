@@ -8,56 +8,56 @@ use euclid::Rect;
 use euclid::RigidTransform3D;
 use euclid::Transform3D;
 
-#[cfg(feature = "ipc")]
+#[cfg(feature = "serialize")]
 use serde::{Deserialize, Serialize};
 
 /// The coordinate space of the viewer
 /// https://immersive-web.github.io/webxr/#dom-xrreferencespacetype-viewer
 #[derive(Clone, Copy, Debug)]
-#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 pub enum Viewer {}
 
 /// The coordinate space of the floor
 /// https://immersive-web.github.io/webxr/#dom-xrreferencespacetype-local-floor
 #[derive(Clone, Copy, Debug)]
-#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 pub enum Floor {}
 
 /// The coordinate space of the left eye
 /// https://immersive-web.github.io/webxr/#dom-xreye-left
 #[derive(Clone, Copy, Debug)]
-#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 pub enum LeftEye {}
 
 /// The coordinate space of the right eye
 /// https://immersive-web.github.io/webxr/#dom-xreye-right
 #[derive(Clone, Copy, Debug)]
-#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 pub enum RightEye {}
 
 /// The native 3D coordinate space of the device
 /// This is not part of the webvr specification.
 #[derive(Clone, Copy, Debug)]
-#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 pub enum Native {}
 
 /// The normalized device coordinate space, where the display
 /// is from (-1,-1) to (1,1).
 // TODO: are we OK assuming that we can use the same coordinate system for all displays?
 #[derive(Clone, Copy, Debug)]
-#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 pub enum Display {}
 
 /// The unnormalized device coordinate space, where the display
 /// is from (0,0) to (w,h), measured in pixels.
 // TODO: are we OK assuming that we can use the same coordinate system for all displays?
 #[derive(Clone, Copy, Debug)]
-#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 pub enum Viewport {}
 
 /// The coordinate space of an input device
 #[derive(Clone, Copy, Debug)]
-#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 pub enum Input {}
 
 /// For each eye, the transform from the viewer to that eye,
@@ -66,16 +66,67 @@ pub enum Input {}
 /// For mono displays, we hagve a `View<Viewer>` (where the transform is the identity).
 /// https://immersive-web.github.io/webxr/#xrview
 #[derive(Clone, Debug)]
-#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 pub struct View<Eye> {
     pub transform: RigidTransform3D<f32, Viewer, Eye>,
     pub projection: Transform3D<f32, Eye, Display>,
     pub viewport: Rect<i32, Viewport>,
 }
 
+/// The field of view of an eye, as the angles from its forward direction
+/// to the edges of its display, in radians. The left and down angles are
+/// typically negative. The angles need not be symmetric.
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct Fov {
+    pub angle_left: f32,
+    pub angle_right: f32,
+    pub angle_up: f32,
+    pub angle_down: f32,
+}
+
+impl Fov {
+    /// A field of view which is symmetric about the forward direction.
+    pub fn symmetric(vertical_fov: f32, aspect: f32) -> Fov {
+        let angle_up = vertical_fov / 2.0;
+        let angle_right = (angle_up.tan() * aspect).atan();
+        Fov {
+            angle_left: -angle_right,
+            angle_right,
+            angle_up,
+            angle_down: -angle_up,
+        }
+    }
+
+    /// The projection of an eye with this field of view onto its display,
+    /// which may be an off-axis projection.
+    pub fn projection<Eye>(&self, near: f32, far: f32) -> Transform3D<f32, Eye, Display> {
+        // https://github.com/KhronosGroup/OpenXR-SDK/blob/release-1.0.5/src/common/xr_linear.h#L621
+        let tan_left = self.angle_left.tan();
+        let tan_right = self.angle_right.tan();
+        let tan_up = self.angle_up.tan();
+        let tan_down = self.angle_down.tan();
+        let width = tan_right - tan_left;
+        let height = tan_up - tan_down;
+        let nf = 1.0 / (near - far);
+
+        // Dear rustfmt, This is a 4x4 matrix, please leave it alone. Best, ajeffrey.
+        {
+            #[rustfmt::skip]
+            // Sigh, row-major vs column-major
+            return Transform3D::row_major(
+                2.0 / width,                     0.0,                            0.0,                   0.0,
+                0.0,                             2.0 / height,                   0.0,                   0.0,
+                (tan_right + tan_left) / width,  (tan_up + tan_down) / height,   (far + near) * nf,     -1.0,
+                0.0,                             0.0,                            2.0 * far * near * nf, 0.0,
+            );
+        }
+    }
+}
+
 /// Whether a device is mono or stereo, and the views it supports.
 #[derive(Clone, Debug)]
-#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 pub enum Views {
     Mono(View<Viewer>),
     Stereo(View<LeftEye>, View<RightEye>),
@@ -3,33 +3,36 @@
  * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
 
 use euclid::default::Size2D as UntypedSize2D;
-use euclid::Angle;
 use euclid::Point2D;
 use euclid::Rect;
 use euclid::RigidTransform3D;
 use euclid::Size2D;
-use euclid::Transform3D;
-use euclid::Trig;
 use euclid::Vector3D;
 
 use gleam::gl;
+use gleam::gl::GLint;
 use gleam::gl::GLsizei;
 use gleam::gl::GLsync;
 use gleam::gl::GLuint;
 use gleam::gl::Gl;
 
-use glutin::EventsLoop;
-use glutin::EventsLoopClosed;
+use glutin::ElementState;
+use glutin::KeyboardInput;
+use glutin::VirtualKeyCode;
+use glutin::WindowEvent;
 
+use std::mem;
 use std::rc::Rc;
 
 use webxr_api::Device;
+use webxr_api::DeviceInfo;
 use webxr_api::Discovery;
-use webxr_api::Display;
+use webxr_api::EndReason;
 use webxr_api::Error;
 use webxr_api::Event;
 use webxr_api::EventBuffer;
 use webxr_api::Floor;
+use webxr_api::Fov;
 use webxr_api::Frame;
 use webxr_api::InputSource;
 use webxr_api::Native;
@@ -38,32 +41,128 @@ use webxr_api::Sender;
 use webxr_api::Session;
 use webxr_api::SessionBuilder;
 use webxr_api::SessionMode;
+use webxr_api::TargetRayMode;
 use webxr_api::View;
+use webxr_api::Viewer;
+use webxr_api::Viewport;
 use webxr_api::Views;
 
-const HEIGHT: f32 = 1.0;
-const EYE_DISTANCE: f32 = 0.25;
-const NEAR: f32 = 0.1;
-const FAR: f32 = 100.0;
+mod mouse;
+mod navigation;
+mod shader;
+
+use mouse::MouseController;
+use navigation::Navigation;
+use shader::GlWindowShader;
+
+// https://immersive-web.github.io/webxr/#dom-xrrenderstate-inlineverticalfieldofview
+const INLINE_VERTICAL_FOV: f32 = std::f32::consts::FRAC_PI_2;
 
 pub trait GlWindow {
     fn make_current(&mut self);
     fn swap_buffers(&mut self);
     fn size(&self) -> UntypedSize2D<GLsizei>;
+    /// The ratio of device pixels to logical pixels, used to place the mouse cursor.
+    fn hidpi_factor(&self) -> f64 {
+        1.0
+    }
+    #[allow(clippy::result_unit_err)]
     fn new_window(&self) -> Result<Box<dyn GlWindow>, ()>;
+
+    /// Whether the window has been closed, or the user has asked for it to be closed.
+    /// Embedders which handle the window's events themselves should report close requests here.
+    fn close_requested(&self) -> bool {
+        false
+    }
+
+    /// The window events received since the last call, which are used to navigate
+    /// and to emulate a controller. The events loop a window was built with is the only
+    /// one which receives its events, so whoever owns it must pass the events on here,
+    /// or keyboard and mouse input does nothing.
+    fn poll_events(&mut self) -> Vec<WindowEvent>;
+}
+
+/// How the stereo views are presented in the window.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum GlWindowMode {
+    /// The left eye on the left half of the window, the right eye on the right half.
+    SideBySide,
+    /// The left eye on the top half of the window, the right eye on the bottom half.
+    TopBottom,
+    /// A single view covering the whole window.
+    Mono,
+    /// Both eyes covering the whole window, the left in red and the right in cyan.
+    Anaglyph,
+    /// Both eyes covering the whole window, the left on even rows and the right on odd rows.
+    Interlaced,
+}
+
+/// Configuration for the simulated headset.
+#[derive(Clone, Debug)]
+pub struct GlWindowConfig {
+    pub mode: GlWindowMode,
+    /// The distance between the eyes, in meters.
+    pub ipd: f32,
+    /// The vertical field of view of each eye, in radians.
+    pub fov: f32,
+    /// The distance from the eyes to the virtual screen, in meters.
+    /// This determines how off-center each eye's projection is.
+    pub screen_distance: f32,
+    pub near: f32,
+    pub far: f32,
+    /// The height of the eyes above the floor, in meters.
+    pub eye_height: f32,
+    /// The lenses to correct for, if the window is being viewed through a headset such as Cardboard.
+    /// This is ignored by the anaglyph and interlaced modes.
+    pub lens: Option<GlWindowLens>,
+}
+
+/// Lens correction parameters.
+#[derive(Clone, Debug)]
+pub struct GlWindowLens {
+    /// The radial distortion coefficients, in the format of the Cardboard viewer profile.
+    /// A point at distance r from the center of the lens is drawn at
+    /// r * (1 + k1 * r^2 + k2 * r^4 + ...), where r is measured in units of
+    /// half the eye's viewport. At most four coefficients are used.
+    /// https://github.com/googlevr/cardboard/blob/master/proto/cardboard_device.proto
+    pub distortion_coefficients: Vec<f32>,
+    /// The scale of the distortion for the red, green and blue channels,
+    /// to correct for chromatic aberration.
+    pub chromatic_aberration: [f32; 3],
+}
+
+impl Default for GlWindowConfig {
+    fn default() -> GlWindowConfig {
+        GlWindowConfig {
+            mode: GlWindowMode::SideBySide,
+            ipd: 0.5,
+            fov: std::f32::consts::FRAC_PI_2,
+            screen_distance: 2.0,
+            near: 0.1,
+            far: 100.0,
+            eye_height: 1.0,
+            lens: None,
+        }
+    }
 }
 
 pub struct GlWindowDiscovery {
     gl: Rc<dyn Gl>,
     factory: Box<dyn Fn() -> Result<Box<dyn GlWindow>, ()>>,
+    config: GlWindowConfig,
 }
 
 impl GlWindowDiscovery {
     pub fn new(
         gl: Rc<dyn Gl>,
         factory: Box<dyn Fn() -> Result<Box<dyn GlWindow>, ()>>,
+        config: GlWindowConfig,
     ) -> GlWindowDiscovery {
-        GlWindowDiscovery { gl, factory }
+        GlWindowDiscovery {
+            gl,
+            factory,
+            config,
+        }
     }
 }
 
@@ -71,15 +170,21 @@ impl Discovery for GlWindowDiscovery {
     fn request_session(&mut self, mode: SessionMode, xr: SessionBuilder) -> Result<Session, Error> {
         if self.supports_session(mode) {
             let gl = self.gl.clone();
-            let window = (self.factory)().or(Err(Error::NoMatchingDevice))?;
-            xr.run_on_main_thread(move || GlWindowDevice::new(gl, window))
+            let window = (self.factory)()
+                .map_err(|_| Error::DeviceInitFailed("Failed to create window".into()))?;
+            let config = self.config.clone();
+            xr.run_on_main_thread(move || GlWindowDevice::new(gl, window, mode, config))
         } else {
-            Err(Error::NoMatchingDevice)
+            Err(Error::UnsupportedMode(mode))
         }
     }
 
     fn supports_session(&self, mode: SessionMode) -> bool {
-        mode == SessionMode::ImmersiveVR
+        mode == SessionMode::ImmersiveVR || mode == SessionMode::Inline
+    }
+
+    fn device_info(&self) -> DeviceInfo {
+        DeviceInfo::new("GL window", "webxr", self)
     }
 }
 
@@ -87,29 +192,68 @@ pub struct GlWindowDevice {
     gl: Rc<dyn Gl>,
     window: Box<dyn GlWindow>,
     read_fbo: GLuint,
+    shader: Option<GlWindowShader>,
     events: EventBuffer,
+    mode: SessionMode,
+    config: GlWindowConfig,
+    navigation: Navigation,
+    mouse: MouseController,
+    quitter: Option<Quitter>,
+    closed: bool,
+}
+
+impl Drop for GlWindowDevice {
+    fn drop(&mut self) {
+        // Once the window is closed, our GL objects have been deleted, or went with its context.
+        if self.closed {
+            return;
+        }
+        self.window.make_current();
+        self.gl.delete_framebuffers(&[self.read_fbo]);
+        self.shader = None;
+    }
 }
 
 impl Device for GlWindowDevice {
     fn floor_transform(&self) -> RigidTransform3D<f32, Native, Floor> {
-        let translation = Vector3D::new(-HEIGHT, 0.0, 0.0);
+        let translation = Vector3D::new(0.0, self.config.eye_height, 0.0);
         RigidTransform3D::from_translation(translation)
     }
 
     fn views(&self) -> Views {
-        let left = self.view(false);
-        let right = self.view(true);
-        Views::Stereo(left, right)
+        if self.mode == SessionMode::Inline || self.config.mode == GlWindowMode::Mono {
+            Views::Mono(self.mono_view())
+        } else {
+            let left = self.view(false);
+            let right = self.view(true);
+            Views::Stereo(left, right)
+        }
     }
 
     fn wait_for_animation_frame(&mut self) -> Frame {
-        self.window.swap_buffers();
-        let translation = Vector3D::new(0.0, 0.0, -5.0);
-        let transform = RigidTransform3D::from_translation(translation);
-        Frame {
-            transform,
-            inputs: vec![],
+        if !self.closed {
+            self.window.swap_buffers();
+            self.handle_window_events();
         }
+        // Inline sessions have no viewer tracking.
+        let transform = if self.mode == SessionMode::Inline {
+            RigidTransform3D::identity()
+        } else {
+            self.navigation.update();
+            self.navigation.transform()
+        };
+        // Once the window is closed, there is nothing for the mouse to point at.
+        if self.closed {
+            let inputs = vec![];
+            return Frame { transform, inputs };
+        }
+        let height = self.window.size().height;
+        let inputs = self
+            .mouse
+            .frame(&self.views(), height, &transform)
+            .into_iter()
+            .collect();
+        Frame { transform, inputs }
     }
 
     fn render_animation_frame(
@@ -118,25 +262,23 @@ impl Device for GlWindowDevice {
         size: UntypedSize2D<i32>,
         sync: Option<GLsync>,
     ) {
-        self.window.make_current();
+        if !self.begin_frame(sync) {
+            return;
+        }
 
         let width = size.width as GLsizei;
         let height = size.height as GLsizei;
         let inner_size = self.window.size();
 
-        self.gl.clear_color(0.2, 0.3, 0.3, 1.0);
-        self.gl.clear(gl::COLOR_BUFFER_BIT);
-        debug_assert_eq!(self.gl.get_error(), gl::NO_ERROR);
-
-        if let Some(sync) = sync {
-            self.gl.wait_sync(sync, 0, gl::TIMEOUT_IGNORED);
-            debug_assert_eq!(self.gl.get_error(), gl::NO_ERROR);
+        if let Some(ref shader) = self.shader {
+            let (left, right) = match self.views() {
+                Views::Mono(view) => (view.viewport, view.viewport),
+                Views::Stereo(left, right) => (left.viewport, right.viewport),
+            };
+            shader.draw_texture(texture_id, size, left, right, inner_size);
+            return;
         }
 
-        self.gl
-            .bind_framebuffer(gl::READ_FRAMEBUFFER, self.read_fbo);
-        debug_assert_eq!(self.gl.get_error(), gl::NO_ERROR);
-
         self.gl.framebuffer_texture_2d(
             gl::READ_FRAMEBUFFER,
             gl::COLOR_ATTACHMENT0,
@@ -162,51 +304,235 @@ impl Device for GlWindowDevice {
         debug_assert_eq!(self.gl.get_error(), gl::NO_ERROR);
     }
 
+    fn supports_multiview(&self) -> bool {
+        // The shaders only sample from 2D textures.
+        self.shader.is_none()
+    }
+
+    fn render_multiview_animation_frame(
+        &mut self,
+        texture_id: u32,
+        size: UntypedSize2D<i32>,
+        sync: Option<GLsync>,
+    ) {
+        if !self.begin_frame(sync) {
+            return;
+        }
+
+        let width = size.width as GLsizei;
+        let height = size.height as GLsizei;
+        let viewports = match self.views() {
+            Views::Mono(view) => vec![view.viewport],
+            Views::Stereo(left, right) => vec![left.viewport, right.viewport],
+        };
+
+        for (layer, viewport) in viewports.iter().enumerate() {
+            self.gl.framebuffer_texture_layer(
+                gl::READ_FRAMEBUFFER,
+                gl::COLOR_ATTACHMENT0,
+                texture_id,
+                0,
+                layer as GLint,
+            );
+            debug_assert_eq!(self.gl.get_error(), gl::NO_ERROR);
+
+            self.gl.blit_framebuffer(
+                0,
+                0,
+                width,
+                height,
+                viewport.min_x(),
+                viewport.min_y(),
+                viewport.max_x(),
+                viewport.max_y(),
+                gl::COLOR_BUFFER_BIT,
+                gl::NEAREST,
+            );
+            debug_assert_eq!(self.gl.get_error(), gl::NO_ERROR);
+        }
+    }
+
     fn initial_inputs(&self) -> Vec<InputSource> {
-        vec![]
+        vec![self.mouse.source()]
     }
 
     fn set_event_dest(&mut self, dest: Sender<Event>) {
         self.events.upgrade(dest)
     }
 
-    fn quit(&mut self) {
-        self.events.callback(Event::SessionEnd);
+    fn quit(&mut self, reason: EndReason) {
+        self.events.callback(Event::SessionEnd(reason));
     }
 
-    fn set_quitter(&mut self, _: Quitter) {
-        // Glwindow currently doesn't have any way to end its own session
-        // XXXManishearth add something for this that listens for the window
-        // being closed
+    fn set_quitter(&mut self, quitter: Quitter) {
+        self.quitter = Some(quitter);
     }
 }
 
 impl GlWindowDevice {
-    fn new(gl: Rc<dyn Gl>, mut window: Box<dyn GlWindow>) -> Result<GlWindowDevice, Error> {
+    fn new(
+        gl: Rc<dyn Gl>,
+        mut window: Box<dyn GlWindow>,
+        mode: SessionMode,
+        config: GlWindowConfig,
+    ) -> Result<GlWindowDevice, Error> {
         window.make_current();
         let read_fbo = gl.gen_framebuffers(1)[0];
         debug_assert_eq!(gl.get_error(), gl::NO_ERROR);
 
+        let shader = if mode == SessionMode::Inline {
+            None
+        } else {
+            GlWindowShader::new(gl.clone(), config.mode, config.lens.as_ref())?
+        };
+
+        let navigation = Navigation::new(Vector3D::new(0.0, 0.0, -5.0));
+        let target_ray_mode = if mode == SessionMode::Inline {
+            TargetRayMode::Screen
+        } else {
+            TargetRayMode::TrackedPointer
+        };
+        let mouse = MouseController::new(target_ray_mode, window.hidpi_factor());
+
         Ok(GlWindowDevice {
             gl,
             window,
             read_fbo,
+            shader,
             events: Default::default(),
+            mode,
+            config,
+            navigation,
+            mouse,
+            quitter: None,
+            closed: false,
         })
     }
 
-    fn view<Eye>(&self, is_right: bool) -> View<Eye> {
+    fn handle_window_events(&mut self) {
+        for event in self.window.poll_events() {
+            self.handle_window_event(event);
+        }
+        if self.window.close_requested() {
+            self.close();
+        }
+    }
+
+    fn handle_window_event(&mut self, event: WindowEvent) {
+        match event {
+            WindowEvent::CloseRequested => self.close(),
+            WindowEvent::Destroyed => self.destroyed(),
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::Escape),
+                        ..
+                    },
+                ..
+            } => self.close(),
+            _ => (),
+        }
+        self.navigation.handle_event(&event);
+        self.mouse.handle_event(&event, &mut self.events);
+    }
+
+    // When the user asks to close the window, we delete our GL objects while the
+    // context is still alive, stop touching GL, and end the session.
+    fn close(&mut self) {
+        if self.closed {
+            return;
+        }
+        self.window.make_current();
+        self.gl.delete_framebuffers(&[self.read_fbo]);
+        self.shader = None;
+        self.end_session();
+    }
+
+    // If the window has already been destroyed, our GL objects went with its context,
+    // so we mustn't delete them, and the shader is leaked rather than dropped.
+    fn destroyed(&mut self) {
+        if self.closed {
+            return;
+        }
+        mem::forget(self.shader.take());
+        self.end_session();
+    }
+
+    fn end_session(&mut self) {
+        self.closed = true;
+        if let Some(ref quitter) = self.quitter {
+            quitter.quit(EndReason::UserRequested);
+        }
+    }
+
+    // Returns false if the window has been closed, and there is nothing to render to.
+    fn begin_frame(&mut self, sync: Option<GLsync>) -> bool {
+        if self.closed {
+            return false;
+        }
+
+        self.window.make_current();
+
+        self.gl.clear_color(0.2, 0.3, 0.3, 1.0);
+        self.gl.clear(gl::COLOR_BUFFER_BIT);
+        debug_assert_eq!(self.gl.get_error(), gl::NO_ERROR);
+
+        if let Some(sync) = sync {
+            self.gl.wait_sync(sync, 0, gl::TIMEOUT_IGNORED);
+            debug_assert_eq!(self.gl.get_error(), gl::NO_ERROR);
+        }
+
+        self.gl
+            .bind_framebuffer(gl::READ_FRAMEBUFFER, self.read_fbo);
+        debug_assert_eq!(self.gl.get_error(), gl::NO_ERROR);
+        true
+    }
+
+    fn viewport(&self, is_right: bool) -> Rect<i32, Viewport> {
         let window_size = self.window.size();
-        let viewport_size = Size2D::new(window_size.width / 2, window_size.height);
-        let viewport_x_origin = if is_right { viewport_size.width } else { 0 };
-        let viewport_origin = Point2D::new(viewport_x_origin, 0);
-        let viewport = Rect::new(viewport_origin, viewport_size);
-        let projection = self.perspective(NEAR, FAR);
+        let (size, origin) = match self.config.mode {
+            GlWindowMode::SideBySide | GlWindowMode::Mono => {
+                let size = Size2D::new(window_size.width / 2, window_size.height);
+                let x = if is_right { size.width } else { 0 };
+                (size, Point2D::new(x, 0))
+            }
+            GlWindowMode::TopBottom => {
+                // Viewports have their origin at the bottom left.
+                let size = Size2D::new(window_size.width, window_size.height / 2);
+                let y = if is_right { 0 } else { size.height };
+                (size, Point2D::new(0, y))
+            }
+            GlWindowMode::Anaglyph | GlWindowMode::Interlaced => {
+                // Each eye covers the whole window, so the texture is twice its width.
+                let size = Size2D::from_untyped(window_size);
+                let x = if is_right { size.width } else { 0 };
+                (size, Point2D::new(x, 0))
+            }
+        };
+        Rect::new(origin, size)
+    }
+
+    fn view<Eye>(&self, is_right: bool) -> View<Eye> {
+        let viewport = self.viewport(is_right);
+        let aspect = viewport.size.width as f32 / viewport.size.height as f32;
         let eye_distance = if is_right {
-            EYE_DISTANCE
+            self.config.ipd / 2.0
         } else {
-            -EYE_DISTANCE
+            -self.config.ipd / 2.0
         };
+        // Both eyes look at the same virtual screen, so their frusta are
+        // off-center by the eye's offset from the middle of the screen.
+        let tan_up = (self.config.fov / 2.0).tan();
+        let tan_right = tan_up * aspect;
+        let shift = eye_distance / self.config.screen_distance;
+        let fov = Fov {
+            angle_left: (-tan_right - shift).atan(),
+            angle_right: (tan_right - shift).atan(),
+            angle_up: tan_up.atan(),
+            angle_down: -tan_up.atan(),
+        };
+        let projection = fov.projection(self.config.near, self.config.far);
         let translation = Vector3D::new(eye_distance, 0.0, 0.0);
         let transform = RigidTransform3D::from_translation(translation);
         View {
@@ -216,28 +542,22 @@ impl GlWindowDevice {
         }
     }
 
-    fn perspective<Eye>(&self, near: f32, far: f32) -> Transform3D<f32, Eye, Display> {
-        // https://github.com/toji/gl-matrix/blob/bd3307196563fbb331b40fc6ebecbbfcc2a4722c/src/mat4.js#L1271
-        let size = self.window.size();
-        let width = size.width as f32;
-        let height = size.height as f32;
-        let fov_up = Angle::radians(f32::fast_atan2(2.0 * height, width));
-        let f = 1.0 / fov_up.radians.tan();
-        let nf = 1.0 / (near - far);
-        let aspect = (width / 2.0) / height;
-
-        // Dear rustfmt, This is a 4x4 matrix, please leave it alone. Best, ajeffrey.
-        {
-            #[rustfmt::skip]
-            // Sigh, row-major vs column-major
-            return Transform3D::row_major(
-                f / aspect, 0.0, 0.0,                   0.0,
-                0.0,        f,   0.0,                   0.0,
-                0.0,        0.0, (far + near) * nf,     -1.0,
-                0.0,        0.0, 2.0 * far * near * nf, 0.0,
-            );
+    fn mono_view(&self) -> View<Viewer> {
+        let window_size = self.window.size();
+        let viewport = Rect::new(Point2D::zero(), Size2D::from_untyped(window_size));
+        let width = window_size.width as f32;
+        let height = window_size.height as f32;
+        let fov = if self.mode == SessionMode::Inline {
+            INLINE_VERTICAL_FOV
+        } else {
+            self.config.fov
+        };
+        let aspect = width / height;
+        let projection = Fov::symmetric(fov, aspect).projection(self.config.near, self.config.far);
+        View {
+            transform: RigidTransform3D::identity(),
+            projection,
+            viewport,
         }
     }
 }
-
-pub type EventsLoopFactory = Box<dyn Fn() -> Result<EventsLoop, EventsLoopClosed>>;
@@ -0,0 +1,182 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! A controller emulated with the mouse, for exercising interaction code on a desktop.
+//!
+//! The target ray points from the viewer through the cursor,
+//! the left button selects, and the right button squeezes.
+
+use euclid::Point2D;
+use euclid::Point3D;
+use euclid::RigidTransform3D;
+use euclid::Rotation3D;
+use euclid::Vector3D;
+
+use glutin::dpi::LogicalPosition;
+use glutin::ElementState;
+use glutin::MouseButton;
+use glutin::WindowEvent;
+
+use webxr_api::Event;
+use webxr_api::EventBuffer;
+use webxr_api::Handedness;
+use webxr_api::Input;
+use webxr_api::InputFrame;
+use webxr_api::InputId;
+use webxr_api::InputSource;
+use webxr_api::Native;
+use webxr_api::SelectEvent;
+use webxr_api::SelectKind;
+use webxr_api::TargetRayMode;
+use webxr_api::View;
+use webxr_api::Viewer;
+use webxr_api::Views;
+
+pub(crate) struct MouseController {
+    source: InputSource,
+    cursor: Option<LogicalPosition>,
+    hidpi_factor: f64,
+    select: bool,
+    squeeze: bool,
+}
+
+impl MouseController {
+    pub(crate) fn new(target_ray_mode: TargetRayMode, hidpi_factor: f64) -> MouseController {
+        let source = InputSource {
+            handedness: Handedness::None,
+            target_ray_mode,
+            id: InputId(0),
+        };
+        MouseController {
+            source,
+            cursor: None,
+            hidpi_factor,
+            select: false,
+            squeeze: false,
+        }
+    }
+
+    pub(crate) fn source(&self) -> InputSource {
+        self.source
+    }
+
+    pub(crate) fn handle_event(&mut self, event: &WindowEvent, events: &mut EventBuffer) {
+        match *event {
+            WindowEvent::MouseInput { state, button, .. } => {
+                let pressed = state == ElementState::Pressed;
+                match button {
+                    MouseButton::Left => self.set_pressed(SelectKind::Select, pressed, events),
+                    MouseButton::Right => self.set_pressed(SelectKind::Squeeze, pressed, events),
+                    _ => (),
+                }
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                self.cursor = Some(position);
+            }
+            WindowEvent::HiDpiFactorChanged(hidpi_factor) => {
+                self.hidpi_factor = hidpi_factor;
+            }
+            WindowEvent::CursorLeft { .. } => {
+                self.cancel_all(events);
+                self.cursor = None;
+            }
+            WindowEvent::Focused(false) => {
+                self.cancel_all(events);
+            }
+            _ => (),
+        }
+    }
+
+    /// The input frame for the current cursor position, given the current views
+    /// and viewer pose. The target ray starts at the viewer, and points through
+    /// the cursor using the projection of the view the cursor is in.
+    pub(crate) fn frame(
+        &self,
+        views: &Views,
+        window_height: i32,
+        viewer: &RigidTransform3D<f32, Viewer, Native>,
+    ) -> Option<InputFrame> {
+        let cursor = self.cursor?;
+        // Cursor positions are logical with the origin at the top left,
+        // viewports are in device pixels with the origin at the bottom left.
+        let x = (cursor.x * self.hidpi_factor) as f32;
+        let y = window_height as f32 - (cursor.y * self.hidpi_factor) as f32;
+        let direction = match *views {
+            Views::Mono(ref view) => ray_direction(view, x, y),
+            Views::Stereo(ref left, ref right) => {
+                ray_direction(left, x, y).or_else(|| ray_direction(right, x, y))
+            }
+        }?;
+
+        // The target ray points along the -Z axis of the input space.
+        let forward = Vector3D::new(0.0, 0.0, -1.0);
+        let axis = forward.cross(direction);
+        let rotation: Rotation3D<f32, Input, Viewer> = if axis.square_length() > std::f32::EPSILON {
+            Rotation3D::around_axis(axis.cast_unit(), forward.angle_to(direction))
+        } else {
+            Rotation3D::identity()
+        };
+        let target_ray_origin = RigidTransform3D::from_rotation(rotation).post_transform(viewer);
+
+        Some(InputFrame {
+            id: self.source.id,
+            target_ray_origin,
+        })
+    }
+
+    fn set_pressed(&mut self, kind: SelectKind, pressed: bool, events: &mut EventBuffer) {
+        let state = match kind {
+            SelectKind::Select => &mut self.select,
+            SelectKind::Squeeze => &mut self.squeeze,
+        };
+        if *state == pressed {
+            return;
+        }
+        *state = pressed;
+        let event = if pressed {
+            SelectEvent::Start
+        } else {
+            SelectEvent::Select
+        };
+        events.callback(Event::Select(self.source.id, kind, event));
+    }
+
+    // We won't see the button releases, so cancel any actions in progress.
+    fn cancel_all(&mut self, events: &mut EventBuffer) {
+        self.cancel(SelectKind::Select, events);
+        self.cancel(SelectKind::Squeeze, events);
+    }
+
+    fn cancel(&mut self, kind: SelectKind, events: &mut EventBuffer) {
+        let state = match kind {
+            SelectKind::Select => &mut self.select,
+            SelectKind::Squeeze => &mut self.squeeze,
+        };
+        if *state {
+            *state = false;
+            events.callback(Event::Select(self.source.id, kind, SelectEvent::End));
+        }
+    }
+}
+
+/// The direction, in viewer coordinates, of the ray through the point (x, y)
+/// of the view's viewport, or `None` if the point is outside the viewport.
+fn ray_direction<Eye>(view: &View<Eye>, x: f32, y: f32) -> Option<Vector3D<f32, Viewer>> {
+    let viewport = view.viewport.to_f32();
+    if !viewport.contains(Point2D::new(x, y)) {
+        return None;
+    }
+    let ndc_x = 2.0 * (x - viewport.min_x()) / viewport.size.width - 1.0;
+    let ndc_y = 2.0 * (y - viewport.min_y()) / viewport.size.height - 1.0;
+    let unproject = view.projection.inverse()?;
+    let near = unproject.transform_point3d(Point3D::new(ndc_x, ndc_y, -1.0))?;
+    let far = unproject.transform_point3d(Point3D::new(ndc_x, ndc_y, 1.0))?;
+    let direction = (far - near).normalize();
+    Some(
+        view.transform
+            .rotation
+            .inverse()
+            .transform_vector3d(direction),
+    )
+}
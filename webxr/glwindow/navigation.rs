@@ -0,0 +1,178 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Keyboard and mouse navigation of the viewer, for walking around a scene without a headset.
+//!
+//! W/S move forward and back, A/D strafe left and right, the arrow keys turn,
+//! and dragging with the middle mouse button looks around.
+
+use euclid::Angle;
+use euclid::RigidTransform3D;
+use euclid::Rotation3D;
+use euclid::Vector3D;
+
+use glutin::dpi::LogicalPosition;
+use glutin::ElementState;
+use glutin::KeyboardInput;
+use glutin::MouseButton;
+use glutin::VirtualKeyCode;
+use glutin::WindowEvent;
+
+use std::f32::consts::FRAC_PI_2;
+
+use webxr_api::Native;
+use webxr_api::Viewer;
+
+// Distance moved per frame, in meters.
+const MOVE_SPEED: f32 = 0.05;
+// Angle turned per frame by the arrow keys, in radians.
+const TURN_SPEED: f32 = 0.02;
+// Angle turned per pixel of mouse movement, in radians.
+const LOOK_SPEED: f32 = 0.005;
+
+pub(crate) struct Navigation {
+    position: Vector3D<f32, Native>,
+    yaw: f32,
+    pitch: f32,
+    forward: bool,
+    back: bool,
+    left: bool,
+    right: bool,
+    turn_left: bool,
+    turn_right: bool,
+    look_up: bool,
+    look_down: bool,
+    looking: bool,
+    cursor: Option<LogicalPosition>,
+}
+
+impl Navigation {
+    pub(crate) fn new(position: Vector3D<f32, Native>) -> Navigation {
+        Navigation {
+            position,
+            yaw: 0.0,
+            pitch: 0.0,
+            forward: false,
+            back: false,
+            left: false,
+            right: false,
+            turn_left: false,
+            turn_right: false,
+            look_up: false,
+            look_down: false,
+            looking: false,
+            cursor: None,
+        }
+    }
+
+    pub(crate) fn handle_event(&mut self, event: &WindowEvent) {
+        match *event {
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state,
+                        virtual_keycode: Some(key),
+                        ..
+                    },
+                ..
+            } => {
+                let pressed = state == ElementState::Pressed;
+                match key {
+                    VirtualKeyCode::W => self.forward = pressed,
+                    VirtualKeyCode::S => self.back = pressed,
+                    VirtualKeyCode::A => self.left = pressed,
+                    VirtualKeyCode::D => self.right = pressed,
+                    VirtualKeyCode::Left => self.turn_left = pressed,
+                    VirtualKeyCode::Right => self.turn_right = pressed,
+                    VirtualKeyCode::Up => self.look_up = pressed,
+                    VirtualKeyCode::Down => self.look_down = pressed,
+                    _ => (),
+                }
+            }
+            WindowEvent::MouseInput {
+                state,
+                button: MouseButton::Middle,
+                ..
+            } => {
+                self.looking = state == ElementState::Pressed;
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                if let (true, Some(cursor)) = (self.looking, self.cursor) {
+                    let dx = (position.x - cursor.x) as f32;
+                    let dy = (position.y - cursor.y) as f32;
+                    self.turn(-dx * LOOK_SPEED, -dy * LOOK_SPEED);
+                }
+                self.cursor = Some(position);
+            }
+            WindowEvent::Focused(false) => {
+                // We won't see the key releases, so stop moving.
+                self.release_all();
+            }
+            _ => (),
+        }
+    }
+
+    /// Move the viewer by one frame's worth of the current key state.
+    pub(crate) fn update(&mut self) {
+        let mut yaw = 0.0;
+        let mut pitch = 0.0;
+        if self.turn_left {
+            yaw += TURN_SPEED;
+        }
+        if self.turn_right {
+            yaw -= TURN_SPEED;
+        }
+        if self.look_up {
+            pitch += TURN_SPEED;
+        }
+        if self.look_down {
+            pitch -= TURN_SPEED;
+        }
+        self.turn(yaw, pitch);
+
+        // Movement is relative to the direction the viewer is facing,
+        // but stays in the horizontal plane.
+        let mut movement = Vector3D::zero();
+        if self.forward {
+            movement.z -= MOVE_SPEED;
+        }
+        if self.back {
+            movement.z += MOVE_SPEED;
+        }
+        if self.left {
+            movement.x -= MOVE_SPEED;
+        }
+        if self.right {
+            movement.x += MOVE_SPEED;
+        }
+        let heading: Rotation3D<f32, Viewer, Native> =
+            Rotation3D::around_y(Angle::radians(self.yaw));
+        self.position += heading.transform_vector3d(movement);
+    }
+
+    /// The transform from the viewer to native coordinates.
+    pub(crate) fn transform(&self) -> RigidTransform3D<f32, Viewer, Native> {
+        let pitch: Rotation3D<f32, Viewer, Viewer> =
+            Rotation3D::around_x(Angle::radians(self.pitch));
+        let yaw: Rotation3D<f32, Viewer, Native> = Rotation3D::around_y(Angle::radians(self.yaw));
+        RigidTransform3D::new(pitch.post_rotate(&yaw), self.position)
+    }
+
+    fn release_all(&mut self) {
+        self.forward = false;
+        self.back = false;
+        self.left = false;
+        self.right = false;
+        self.turn_left = false;
+        self.turn_right = false;
+        self.look_up = false;
+        self.look_down = false;
+        self.looking = false;
+    }
+
+    fn turn(&mut self, yaw: f32, pitch: f32) {
+        self.yaw += yaw;
+        self.pitch = (self.pitch + pitch).max(-FRAC_PI_2).min(FRAC_PI_2);
+    }
+}
@@ -0,0 +1,267 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Display modes and lens correction which can't be implemented by blitting the texture,
+//! and instead draw it to the window with a shader.
+
+use crate::glwindow::GlWindowLens;
+use crate::glwindow::GlWindowMode;
+
+use euclid::default::Size2D as UntypedSize2D;
+use euclid::Rect;
+
+use gleam::gl;
+use gleam::gl::GLenum;
+use gleam::gl::GLint;
+use gleam::gl::GLsizei;
+use gleam::gl::GLuint;
+use gleam::gl::Gl;
+use gleam::gl::GlType;
+
+use std::rc::Rc;
+
+use webxr_api::Error;
+use webxr_api::Viewport;
+
+// A full-window quad, drawn as a triangle strip without any vertex buffers.
+const VERTEX_SHADER: &str = "
+    out vec2 v_uv;
+    void main() {
+        v_uv = vec2(float(gl_VertexID & 1), float((gl_VertexID & 2) >> 1));
+        gl_Position = vec4(v_uv * 2.0 - 1.0, 0.0, 1.0);
+    }
+";
+
+// Red from the left eye, green and blue from the right eye.
+const ANAGLYPH_FRAGMENT_SHADER: &str = "
+    uniform sampler2D u_texture;
+    uniform vec4 u_left;
+    uniform vec4 u_right;
+    in vec2 v_uv;
+    out vec4 o_color;
+    void main() {
+        vec4 left = texture(u_texture, u_left.xy + v_uv * u_left.zw);
+        vec4 right = texture(u_texture, u_right.xy + v_uv * u_right.zw);
+        o_color = vec4(left.r, right.g, right.b, 1.0);
+    }
+";
+
+// Even rows from the left eye, odd rows from the right eye.
+const INTERLACED_FRAGMENT_SHADER: &str = "
+    uniform sampler2D u_texture;
+    uniform vec4 u_left;
+    uniform vec4 u_right;
+    in vec2 v_uv;
+    out vec4 o_color;
+    void main() {
+        vec4 rect = (mod(floor(gl_FragCoord.y), 2.0) < 1.0) ? u_left : u_right;
+        o_color = texture(u_texture, rect.xy + v_uv * rect.zw);
+    }
+";
+
+// Barrel distortion, applied separately to each color channel.
+// Each eye is drawn to its own region of the window.
+const DISTORTION_FRAGMENT_SHADER: &str = "
+    uniform sampler2D u_texture;
+    uniform vec4 u_left;
+    uniform vec4 u_right;
+    uniform vec4 u_left_out;
+    uniform vec4 u_right_out;
+    uniform vec4 u_coefficients;
+    uniform vec3 u_chromatic_aberration;
+    in vec2 v_uv;
+    out vec4 o_color;
+
+    vec4 lookup(vec4 rect, vec2 p, float scale) {
+        float r2 = dot(p, p);
+        vec4 k = u_coefficients;
+        vec2 q = p * scale * (1.0 + r2 * (k.x + r2 * (k.y + r2 * (k.z + r2 * k.w))));
+        if (any(greaterThan(abs(q), vec2(1.0)))) {
+            return vec4(0.0);
+        }
+        return texture(u_texture, rect.xy + (q * 0.5 + 0.5) * rect.zw);
+    }
+
+    void main() {
+        bool is_left = all(greaterThanEqual(v_uv, u_left_out.xy)) &&
+                       all(lessThan(v_uv, u_left_out.xy + u_left_out.zw));
+        vec4 rect = is_left ? u_left : u_right;
+        vec4 out_rect = is_left ? u_left_out : u_right_out;
+        vec2 p = (v_uv - out_rect.xy) / out_rect.zw * 2.0 - 1.0;
+        o_color = vec4(
+            lookup(rect, p, u_chromatic_aberration.r).r,
+            lookup(rect, p, u_chromatic_aberration.g).g,
+            lookup(rect, p, u_chromatic_aberration.b).b,
+            1.0
+        );
+    }
+";
+
+pub(crate) struct GlWindowShader {
+    gl: Rc<dyn Gl>,
+    program: GLuint,
+    vao: GLuint,
+    texture_location: GLint,
+    left_location: GLint,
+    right_location: GLint,
+    left_out_location: GLint,
+    right_out_location: GLint,
+}
+
+impl GlWindowShader {
+    /// The shader for the given mode and lens, or `None` if the texture can just be blitted.
+    pub(crate) fn new(
+        gl: Rc<dyn Gl>,
+        mode: GlWindowMode,
+        lens: Option<&GlWindowLens>,
+    ) -> Result<Option<GlWindowShader>, Error> {
+        // Lens correction isn't combined with the other shaders.
+        let (fragment_shader, lens) = match (mode, lens) {
+            (GlWindowMode::Anaglyph, _) => (ANAGLYPH_FRAGMENT_SHADER, None),
+            (GlWindowMode::Interlaced, _) => (INTERLACED_FRAGMENT_SHADER, None),
+            (_, Some(lens)) => (DISTORTION_FRAGMENT_SHADER, Some(lens)),
+            (_, None) => return Ok(None),
+        };
+
+        let vertex_shader = compile_shader(&*gl, gl::VERTEX_SHADER, VERTEX_SHADER)?;
+        let fragment_shader = match compile_shader(&*gl, gl::FRAGMENT_SHADER, fragment_shader) {
+            Ok(fragment_shader) => fragment_shader,
+            Err(err) => {
+                gl.delete_shader(vertex_shader);
+                return Err(err);
+            }
+        };
+
+        let program = gl.create_program();
+        gl.attach_shader(program, vertex_shader);
+        gl.attach_shader(program, fragment_shader);
+        gl.link_program(program);
+        gl.delete_shader(vertex_shader);
+        gl.delete_shader(fragment_shader);
+
+        let mut status = [0];
+        unsafe { gl.get_program_iv(program, gl::LINK_STATUS, &mut status) };
+        if status[0] == 0 {
+            let log = gl.get_program_info_log(program);
+            gl.delete_program(program);
+            return Err(Error::DeviceInitFailed(format!(
+                "Failed to link shader: {}",
+                log
+            )));
+        }
+
+        let texture_location = gl.get_uniform_location(program, "u_texture");
+        let left_location = gl.get_uniform_location(program, "u_left");
+        let right_location = gl.get_uniform_location(program, "u_right");
+        let left_out_location = gl.get_uniform_location(program, "u_left_out");
+        let right_out_location = gl.get_uniform_location(program, "u_right_out");
+
+        // The lens parameters don't change, so we can set them once.
+        if let Some(lens) = lens {
+            let mut coefficients = [0.0; 4];
+            for (k, coefficient) in coefficients.iter_mut().zip(&lens.distortion_coefficients) {
+                *k = *coefficient;
+            }
+            gl.use_program(program);
+            let location = gl.get_uniform_location(program, "u_coefficients");
+            gl.uniform_4fv(location, &coefficients);
+            let location = gl.get_uniform_location(program, "u_chromatic_aberration");
+            gl.uniform_3f(
+                location,
+                lens.chromatic_aberration[0],
+                lens.chromatic_aberration[1],
+                lens.chromatic_aberration[2],
+            );
+            gl.use_program(0);
+        }
+
+        let vao = gl.gen_vertex_arrays(1)[0];
+        debug_assert_eq!(gl.get_error(), gl::NO_ERROR);
+
+        Ok(Some(GlWindowShader {
+            gl,
+            program,
+            vao,
+            texture_location,
+            left_location,
+            right_location,
+            left_out_location,
+            right_out_location,
+        }))
+    }
+
+    /// Draw the texture to the whole of the current framebuffer, which has the given size.
+    /// The eye viewports are the regions of the texture that each eye was rendered to,
+    /// and for lens correction, the regions of the window that each eye is drawn to.
+    pub(crate) fn draw_texture(
+        &self,
+        texture_id: GLuint,
+        texture_size: UntypedSize2D<GLsizei>,
+        left: Rect<i32, Viewport>,
+        right: Rect<i32, Viewport>,
+        window_size: UntypedSize2D<GLsizei>,
+    ) {
+        let uv_rect = |rect: Rect<i32, Viewport>, size: UntypedSize2D<GLsizei>| {
+            let width = size.width as f32;
+            let height = size.height as f32;
+            [
+                rect.origin.x as f32 / width,
+                rect.origin.y as f32 / height,
+                rect.size.width as f32 / width,
+                rect.size.height as f32 / height,
+            ]
+        };
+
+        self.gl
+            .viewport(0, 0, window_size.width, window_size.height);
+        self.gl.use_program(self.program);
+        self.gl.active_texture(gl::TEXTURE0);
+        self.gl.bind_texture(gl::TEXTURE_2D, texture_id);
+        self.gl.uniform_1i(self.texture_location, 0);
+        self.gl
+            .uniform_4fv(self.left_location, &uv_rect(left, texture_size));
+        self.gl
+            .uniform_4fv(self.right_location, &uv_rect(right, texture_size));
+        // These are -1 for shaders other than lens correction, so setting them is a no-op.
+        self.gl
+            .uniform_4fv(self.left_out_location, &uv_rect(left, window_size));
+        self.gl
+            .uniform_4fv(self.right_out_location, &uv_rect(right, window_size));
+        self.gl.bind_vertex_array(self.vao);
+        self.gl.draw_arrays(gl::TRIANGLE_STRIP, 0, 4);
+        debug_assert_eq!(self.gl.get_error(), gl::NO_ERROR);
+
+        self.gl.bind_vertex_array(0);
+        self.gl.bind_texture(gl::TEXTURE_2D, 0);
+        self.gl.use_program(0);
+    }
+}
+
+impl Drop for GlWindowShader {
+    fn drop(&mut self) {
+        self.gl.delete_vertex_arrays(&[self.vao]);
+        self.gl.delete_program(self.program);
+    }
+}
+
+fn compile_shader(gl: &dyn Gl, shader_type: GLenum, source: &str) -> Result<GLuint, Error> {
+    let header: &[u8] = match gl.get_type() {
+        GlType::Gl => b"#version 150\n",
+        GlType::Gles => b"#version 300 es\nprecision mediump float;\n",
+    };
+    let shader = gl.create_shader(shader_type);
+    gl.shader_source(shader, &[header, source.as_bytes()]);
+    gl.compile_shader(shader);
+    let mut status = [0];
+    unsafe { gl.get_shader_iv(shader, gl::COMPILE_STATUS, &mut status) };
+    if status[0] == 0 {
+        let log = gl.get_shader_info_log(shader);
+        gl.delete_shader(shader);
+        return Err(Error::DeviceInitFailed(format!(
+            "Failed to compile shader: {}",
+            log
+        )));
+    }
+    Ok(shader)
+}
@@ -3,7 +3,10 @@
  * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
 
 use webxr_api::Device;
+use webxr_api::DeviceInfo;
+use webxr_api::DeviceNotifier;
 use webxr_api::Discovery;
+use webxr_api::EndReason;
 use webxr_api::Error;
 use webxr_api::Event;
 use webxr_api::EventBuffer;
@@ -15,8 +18,11 @@ use webxr_api::InputSource;
 use webxr_api::MockDeviceInit;
 use webxr_api::MockDeviceMsg;
 use webxr_api::MockDiscovery;
+use webxr_api::MockFrameCapture;
 use webxr_api::MockInputMsg;
+use webxr_api::MockViewCapture;
 use webxr_api::Native;
+use webxr_api::PixelBuffer;
 use webxr_api::Quitter;
 use webxr_api::Receiver;
 use webxr_api::Sender;
@@ -24,9 +30,12 @@ use webxr_api::Session;
 use webxr_api::SessionBuilder;
 use webxr_api::SessionMode;
 use webxr_api::Viewer;
+use webxr_api::Viewport;
 use webxr_api::Views;
+use webxr_api::Visibility;
 
 use euclid::default::Size2D;
+use euclid::Rect;
 use euclid::RigidTransform3D;
 
 use gleam::gl;
@@ -34,18 +43,28 @@ use gleam::gl::GLsync;
 use gleam::gl::GLuint;
 use gleam::gl::Gl;
 
+use std::collections::VecDeque;
 use std::rc::Rc;
+use std::sync::mpsc;
 use std::sync::{Arc, Mutex};
 use std::thread;
+use std::thread::JoinHandle;
+
+// The number of frames whose foveation level is kept, so long-running tests use bounded memory.
+const FOVEATION_HISTORY: usize = 1000;
 
 pub struct HeadlessMockDiscovery {
-    gl: Rc<dyn Gl>,
+    gl: Option<Rc<dyn Gl>>,
 }
 
 struct HeadlessDiscovery {
-    gl: Rc<dyn Gl>,
+    gl: Option<Rc<dyn Gl>>,
     data: Arc<Mutex<HeadlessDeviceData>>,
     supports_immersive: bool,
+    supports_unbounded: bool,
+    thread: Option<JoinHandle<()>>,
+    // Stops the thread, even if content still has a sender for it.
+    stop: mpsc::Sender<Option<MockDeviceMsg>>,
 }
 
 struct InputInfo {
@@ -55,8 +74,10 @@ struct InputInfo {
 }
 
 struct HeadlessDevice {
-    gl: Rc<dyn Gl>,
+    gl: Option<Rc<dyn Gl>>,
     data: Arc<Mutex<HeadlessDeviceData>>,
+    foveation_level: f32,
+    read_fbo: Option<GLuint>,
 }
 
 struct HeadlessDeviceData {
@@ -66,7 +87,11 @@ struct HeadlessDeviceData {
     inputs: Vec<InputInfo>,
     events: EventBuffer,
     quitter: Option<Quitter>,
+    notifier: Option<DeviceNotifier>,
     disconnected: bool,
+    foveation_levels: VecDeque<f32>,
+    capture_limit: usize,
+    captures: VecDeque<MockFrameCapture>,
 }
 
 impl MockDiscovery for HeadlessMockDiscovery {
@@ -75,7 +100,7 @@ impl MockDiscovery for HeadlessMockDiscovery {
         init: MockDeviceInit,
         receiver: Receiver<MockDeviceMsg>,
     ) -> Result<Box<dyn Discovery>, Error> {
-        let viewer_origin = init.viewer_origin.clone();
+        let viewer_origin = init.viewer_origin;
         let floor_transform = init.floor_origin.inverse();
         let views = init.views.clone();
         let data = HeadlessDeviceData {
@@ -85,24 +110,41 @@ impl MockDiscovery for HeadlessMockDiscovery {
             inputs: vec![],
             events: Default::default(),
             quitter: None,
+            notifier: None,
             disconnected: false,
+            foveation_levels: VecDeque::new(),
+            capture_limit: 0,
+            captures: VecDeque::new(),
         };
         let data = Arc::new(Mutex::new(data));
         let data_ = data.clone();
 
+        let (sender, local_receiver) = mpsc::channel();
+        let stop = sender.clone();
         thread::spawn(move || {
-            run_loop(receiver, data_);
+            while let Ok(msg) = receiver.recv() {
+                if sender.send(Some(msg)).is_err() {
+                    break;
+                }
+            }
+        });
+        let thread = thread::spawn(move || {
+            run_loop(local_receiver, data_);
         });
         Ok(Box::new(HeadlessDiscovery {
             gl: self.gl.clone(),
             data,
             supports_immersive: init.supports_immersive,
+            supports_unbounded: init.supports_unbounded,
+            thread: Some(thread),
+            stop,
         }))
     }
 }
 
-fn run_loop(receiver: Receiver<MockDeviceMsg>, data: Arc<Mutex<HeadlessDeviceData>>) {
-    while let Ok(msg) = receiver.recv() {
+// Messages from content are forwarded to the thread, and `None` stops it.
+fn run_loop(receiver: mpsc::Receiver<Option<MockDeviceMsg>>, data: Arc<Mutex<HeadlessDeviceData>>) {
+    while let Ok(Some(msg)) = receiver.recv() {
         if !data.lock().expect("Mutex poisoned").handle_msg(msg) {
             break;
         }
@@ -111,22 +153,54 @@ fn run_loop(receiver: Receiver<MockDeviceMsg>, data: Arc<Mutex<HeadlessDeviceDat
 
 impl Discovery for HeadlessDiscovery {
     fn request_session(&mut self, mode: SessionMode, xr: SessionBuilder) -> Result<Session, Error> {
-        if self.data.lock().unwrap().disconnected || !self.supports_session(mode) {
-            return Err(Error::NoMatchingDevice);
+        if self.data.lock().unwrap().disconnected {
+            return Err(Error::DeviceDisconnected);
+        }
+        if !self.supports_session(mode) {
+            return Err(Error::UnsupportedMode(mode));
         }
         let gl = self.gl.clone();
         let data = self.data.clone();
-        xr.run_on_main_thread(move || Ok(HeadlessDevice { gl, data }))
+        xr.run_on_main_thread(move || {
+            Ok(HeadlessDevice {
+                gl,
+                data,
+                foveation_level: 0.0,
+                read_fbo: None,
+            })
+        })
     }
 
     fn supports_session(&self, mode: SessionMode) -> bool {
+        if self.data.lock().unwrap().disconnected {
+            return false;
+        }
         mode == SessionMode::Inline || self.supports_immersive
     }
+
+    fn device_info(&self) -> DeviceInfo {
+        let mut info = DeviceInfo::new("Headless mock device", "webxr", self);
+        if self.supports_unbounded {
+            info.features.push("unbounded".into());
+        }
+        info
+    }
+
+    fn set_device_notifier(&mut self, notifier: DeviceNotifier) {
+        self.data.lock().unwrap().notifier = Some(notifier);
+    }
+
+    fn shutdown(&mut self) {
+        let _ = self.stop.send(None);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
 }
 
 impl Device for HeadlessDevice {
     fn floor_transform(&self) -> RigidTransform3D<f32, Native, Floor> {
-        self.data.lock().unwrap().floor_transform.clone()
+        self.data.lock().unwrap().floor_transform
     }
 
     fn views(&self) -> Views {
@@ -148,11 +222,42 @@ impl Device for HeadlessDevice {
         Frame { transform, inputs }
     }
 
-    fn render_animation_frame(&mut self, _: GLuint, _: Size2D<i32>, sync: Option<GLsync>) {
+    fn render_animation_frame(&mut self, texture_id: GLuint, _: Size2D<i32>, sync: Option<GLsync>) {
+        let gl = match self.gl {
+            Some(ref gl) => gl.clone(),
+            None => return,
+        };
         if let Some(sync) = sync {
-            self.gl.wait_sync(sync, 0, gl::TIMEOUT_IGNORED);
-            debug_assert_eq!(self.gl.get_error(), gl::NO_ERROR);
+            gl.wait_sync(sync, 0, gl::TIMEOUT_IGNORED);
+            debug_assert_eq!(gl.get_error(), gl::NO_ERROR);
         }
+        let capture = if self.capturing() {
+            Some(self.capture_texture(&*gl, texture_id))
+        } else {
+            None
+        };
+        self.record_frame(capture);
+    }
+
+    fn supports_pixel_buffers(&self) -> bool {
+        true
+    }
+
+    fn render_pixel_frame(&mut self, buffer: &PixelBuffer) {
+        let capture = if self.capturing() {
+            Some(self.capture_pixels(buffer))
+        } else {
+            None
+        };
+        self.record_frame(capture);
+    }
+
+    fn supports_foveation(&self) -> bool {
+        true
+    }
+
+    fn set_foveation_level(&mut self, level: f32) {
+        self.foveation_level = level;
     }
 
     fn initial_inputs(&self) -> Vec<InputSource> {
@@ -163,8 +268,12 @@ impl Device for HeadlessDevice {
         self.data.lock().unwrap().events.upgrade(dest)
     }
 
-    fn quit(&mut self) {
-        self.data.lock().unwrap().events.callback(Event::SessionEnd);
+    fn quit(&mut self, reason: EndReason) {
+        self.data
+            .lock()
+            .unwrap()
+            .events
+            .callback(Event::SessionEnd(reason));
     }
 
     fn set_quitter(&mut self, quitter: Quitter) {
@@ -172,9 +281,117 @@ impl Device for HeadlessDevice {
     }
 }
 
+impl HeadlessDevice {
+    fn capturing(&self) -> bool {
+        self.data.lock().unwrap().capture_limit > 0
+    }
+
+    fn viewports(&self) -> Vec<Rect<i32, Viewport>> {
+        match self.views() {
+            Views::Mono(view) => vec![view.viewport],
+            Views::Stereo(left, right) => vec![left.viewport, right.viewport],
+        }
+    }
+
+    fn capture_texture(&mut self, gl: &dyn Gl, texture_id: GLuint) -> MockFrameCapture {
+        let read_fbo = *self
+            .read_fbo
+            .get_or_insert_with(|| gl.gen_framebuffers(1)[0]);
+        gl.bind_framebuffer(gl::READ_FRAMEBUFFER, read_fbo);
+        gl.framebuffer_texture_2d(
+            gl::READ_FRAMEBUFFER,
+            gl::COLOR_ATTACHMENT0,
+            gl::TEXTURE_2D,
+            texture_id,
+            0,
+        );
+        debug_assert_eq!(gl.get_error(), gl::NO_ERROR);
+
+        let views = self
+            .viewports()
+            .into_iter()
+            .map(|viewport| {
+                let pixels = gl.read_pixels(
+                    viewport.origin.x,
+                    viewport.origin.y,
+                    viewport.size.width,
+                    viewport.size.height,
+                    gl::RGBA,
+                    gl::UNSIGNED_BYTE,
+                );
+                MockViewCapture { viewport, pixels }
+            })
+            .collect();
+        debug_assert_eq!(gl.get_error(), gl::NO_ERROR);
+
+        gl.bind_framebuffer(gl::READ_FRAMEBUFFER, 0);
+        MockFrameCapture { views }
+    }
+
+    fn capture_pixels(&self, buffer: &PixelBuffer) -> MockFrameCapture {
+        let views = self
+            .viewports()
+            .into_iter()
+            .map(|viewport| {
+                // Parts of the viewport outside the buffer are left transparent.
+                let width = viewport.size.width.max(0);
+                let height = viewport.size.height.max(0);
+                let mut pixels = vec![0; width as usize * height as usize * 4];
+                let min_x = viewport.origin.x.max(0);
+                let max_x = (viewport.origin.x + width).min(buffer.size.width);
+                let min_y = viewport.origin.y.max(0);
+                let max_y = (viewport.origin.y + height).min(buffer.size.height);
+                let len = (max_x - min_x).max(0) as usize * 4;
+                for y in (min_y..max_y).filter(|_| len > 0) {
+                    let src = (y * buffer.size.width + min_x) as usize * 4;
+                    let dst =
+                        ((y - viewport.origin.y) * width + min_x - viewport.origin.x) as usize * 4;
+                    if let Some(src) = buffer.pixels.get(src..src + len) {
+                        pixels[dst..dst + len].copy_from_slice(src);
+                    }
+                }
+                MockViewCapture { viewport, pixels }
+            })
+            .collect();
+        MockFrameCapture { views }
+    }
+
+    fn record_frame(&mut self, capture: Option<MockFrameCapture>) {
+        let mut data = self.data.lock().unwrap();
+        if data.foveation_levels.len() >= FOVEATION_HISTORY {
+            data.foveation_levels.pop_front();
+        }
+        data.foveation_levels.push_back(self.foveation_level);
+        if let Some(capture) = capture {
+            // Capturing may have been turned off since the frame was read.
+            if data.capture_limit == 0 {
+                return;
+            }
+            while data.captures.len() >= data.capture_limit {
+                data.captures.pop_front();
+            }
+            data.captures.push_back(capture);
+        }
+    }
+}
+
+impl Drop for HeadlessDevice {
+    fn drop(&mut self) {
+        if let (Some(gl), Some(read_fbo)) = (&self.gl, self.read_fbo) {
+            gl.delete_framebuffers(&[read_fbo]);
+        }
+    }
+}
+
 impl HeadlessMockDiscovery {
     pub fn new(gl: Rc<dyn Gl>) -> HeadlessMockDiscovery {
-        HeadlessMockDiscovery { gl }
+        HeadlessMockDiscovery { gl: Some(gl) }
+    }
+
+    /// A mock discovery for machines without GL, whose devices
+    /// can only render content from pixel buffers.
+    pub fn without_gl() -> HeadlessMockDiscovery {
+        HeadlessMockDiscovery { gl: None }
     }
 }
 
@@ -188,10 +405,24 @@ impl HeadlessDeviceData {
                 self.views = views;
             }
             MockDeviceMsg::Focus => {
-                // TODO
+                self.events
+                    .callback(Event::VisibilityChange(Visibility::Visible));
             }
             MockDeviceMsg::Blur => {
-                // TODO
+                self.events
+                    .callback(Event::VisibilityChange(Visibility::VisibleBlurred));
+            }
+            MockDeviceMsg::GetFoveationLevels(s) => {
+                let _ = s.send(self.foveation_levels.iter().cloned().collect());
+            }
+            MockDeviceMsg::CaptureFrames(limit) => {
+                self.capture_limit = limit;
+                while self.captures.len() > limit {
+                    self.captures.pop_front();
+                }
+            }
+            MockDeviceMsg::GetCapturedFrames(s) => {
+                let _ = s.send(self.captures.iter().cloned().collect());
             }
             MockDeviceMsg::AddInputSource(init) => {
                 self.inputs.push(InputInfo {
@@ -207,6 +438,9 @@ impl HeadlessDeviceData {
                         MockInputMsg::SetHandedness(h) => input.source.handedness = h,
                         MockInputMsg::SetTargetRayMode(t) => input.source.target_ray_mode = t,
                         MockInputMsg::SetPointerOrigin(p) => input.pointer = p,
+                        MockInputMsg::TriggerSelect(kind, event) => {
+                            self.events.callback(Event::Select(id, kind, event))
+                        }
                         MockInputMsg::Disconnect => input.active = false,
                         MockInputMsg::Reconnect => input.active = true,
                     }
@@ -214,7 +448,12 @@ impl HeadlessDeviceData {
             }
             MockDeviceMsg::Disconnect(s) => {
                 self.disconnected = true;
-                self.quitter.as_ref().map(|q| q.quit());
+                if let Some(ref quitter) = self.quitter {
+                    quitter.quit(EndReason::DeviceLost);
+                }
+                if let Some(ref mut notifier) = self.notifier {
+                    notifier.disconnected();
+                }
                 // notify the client that we're done disconnecting
                 let _ = s.send(());
                 return false;
@@ -223,3 +462,396 @@ impl HeadlessDeviceData {
         true
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::HeadlessMockDiscovery;
+
+    use webxr_api::Device;
+    use webxr_api::DeviceChange;
+    use webxr_api::Discovery;
+    use webxr_api::EndReason;
+    use webxr_api::Error;
+    use webxr_api::Event;
+    use webxr_api::Floor;
+    use webxr_api::Frame;
+    use webxr_api::InputSource;
+    use webxr_api::MainThreadRegistry;
+    use webxr_api::MainThreadWaker;
+    use webxr_api::MockDeviceInit;
+    use webxr_api::MockDeviceMsg;
+    use webxr_api::MockPermissions;
+    use webxr_api::Native;
+    use webxr_api::PermissionApi;
+    use webxr_api::PermissionRequest;
+    use webxr_api::PermissionResponder;
+    use webxr_api::PixelBuffer;
+    use webxr_api::PixelBufferApi;
+    use webxr_api::PixelBufferId;
+    use webxr_api::Quitter;
+    use webxr_api::Sender;
+    use webxr_api::Session;
+    use webxr_api::SessionBuilder;
+    use webxr_api::SessionInit;
+    use webxr_api::SessionMode;
+    use webxr_api::View;
+    use webxr_api::Views;
+
+    use euclid::default::Size2D as UntypedSize2D;
+    use euclid::Point2D;
+    use euclid::Rect;
+    use euclid::RigidTransform3D;
+    use euclid::Size2D;
+    use euclid::Transform3D;
+
+    use gleam::gl::GLsync;
+
+    use std::sync::atomic::AtomicBool;
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+
+    struct Waker;
+
+    impl MainThreadWaker for Waker {
+        fn clone_box(&self) -> Box<dyn MainThreadWaker> {
+            Box::new(Waker)
+        }
+        fn wake(&self) {}
+    }
+
+    // A 4x2 pixel buffer, whose pixels are their coordinates.
+    struct Pixels;
+
+    impl PixelBufferApi for Pixels {
+        fn lock(&self, _: PixelBufferId) -> Option<PixelBuffer> {
+            let pixels = (0..2)
+                .flat_map(|y| (0..4).map(move |x| vec![x, y, 0, 255]))
+                .flatten()
+                .collect();
+            let size = Size2D::new(4, 2);
+            Some(PixelBuffer { size, pixels })
+        }
+        fn unlock(&self, _: PixelBufferId) {}
+        fn clone_box(&self) -> Box<dyn PixelBufferApi> {
+            Box::new(Pixels)
+        }
+    }
+
+    // Drops each request without answering it.
+    struct IgnorePermissions;
+
+    impl PermissionApi for IgnorePermissions {
+        fn request_permission(&mut self, _: PermissionRequest, _: PermissionResponder) {}
+    }
+
+    // For requests which should fail before the user is asked.
+    struct UnreachablePermissions;
+
+    impl PermissionApi for UnreachablePermissions {
+        fn request_permission(&mut self, request: PermissionRequest, _: PermissionResponder) {
+            panic!("Permission should not be requested for {:?}", request.mode);
+        }
+    }
+
+    // A device whose sessions run on their own thread, which says when it has been dropped.
+    struct ThreadDiscovery {
+        dropped: Arc<AtomicBool>,
+    }
+
+    struct ThreadDevice {
+        dropped: Arc<AtomicBool>,
+    }
+
+    impl Discovery for ThreadDiscovery {
+        fn request_session(
+            &mut self,
+            _: SessionMode,
+            xr: SessionBuilder,
+        ) -> Result<Session, Error> {
+            let dropped = self.dropped.clone();
+            xr.spawn(move || Ok(ThreadDevice { dropped }))
+        }
+
+        fn supports_session(&self, _: SessionMode) -> bool {
+            true
+        }
+    }
+
+    impl Device for ThreadDevice {
+        fn floor_transform(&self) -> RigidTransform3D<f32, Native, Floor> {
+            RigidTransform3D::identity()
+        }
+
+        fn views(&self) -> Views {
+            Views::Mono(view(0, 0, 0, 0))
+        }
+
+        fn wait_for_animation_frame(&mut self) -> Frame {
+            Frame {
+                transform: RigidTransform3D::identity(),
+                inputs: vec![],
+            }
+        }
+
+        fn render_animation_frame(&mut self, _: u32, _: UntypedSize2D<i32>, _: Option<GLsync>) {}
+
+        fn supports_pixel_buffers(&self) -> bool {
+            true
+        }
+
+        fn initial_inputs(&self) -> Vec<InputSource> {
+            vec![]
+        }
+
+        fn set_event_dest(&mut self, _: Sender<Event>) {}
+
+        fn quit(&mut self, _: EndReason) {}
+
+        fn set_quitter(&mut self, _: Quitter) {}
+    }
+
+    impl Drop for ThreadDevice {
+        fn drop(&mut self) {
+            self.dropped.store(true, Ordering::SeqCst);
+        }
+    }
+
+    fn registry() -> MainThreadRegistry {
+        let mut registry = MainThreadRegistry::new(Box::new(Waker)).unwrap();
+        registry.set_pixel_buffers(Box::new(Pixels));
+        registry.register_mock(HeadlessMockDiscovery::without_gl());
+        registry
+    }
+
+    fn view<Eye>(x: i32, y: i32, width: i32, height: i32) -> View<Eye> {
+        View {
+            transform: RigidTransform3D::identity(),
+            projection: Transform3D::identity(),
+            viewport: Rect::new(Point2D::new(x, y), Size2D::new(width, height)),
+        }
+    }
+
+    fn connect(registry: &mut MainThreadRegistry, views: Views) -> Sender<MockDeviceMsg> {
+        let init = MockDeviceInit {
+            floor_origin: RigidTransform3D::identity(),
+            supports_immersive: true,
+            supports_unbounded: false,
+            viewer_origin: RigidTransform3D::identity(),
+            views,
+        };
+        let (sender, receiver) = webxr_api::channel().unwrap();
+        registry.registry().simulate_device_connection(init, sender);
+        registry.run_one_frame();
+        receiver.recv().unwrap().unwrap()
+    }
+
+    fn request_session(
+        registry: &mut MainThreadRegistry,
+        mode: SessionMode,
+    ) -> Result<Session, Error> {
+        request_session_with_init(registry, mode, Default::default())
+    }
+
+    fn request_session_with_init(
+        registry: &mut MainThreadRegistry,
+        mode: SessionMode,
+        init: SessionInit,
+    ) -> Result<Session, Error> {
+        let (sender, receiver) = webxr_api::channel().unwrap();
+        registry.registry().request_session(mode, init, sender);
+        registry.run_one_frame();
+        receiver.recv().unwrap()
+    }
+
+    fn render(registry: &mut MainThreadRegistry, session: &mut Session) {
+        session.render_animation_frame();
+        registry.run_one_frame();
+    }
+
+    #[test]
+    fn foveation_level_is_recorded_for_each_frame() {
+        let mut registry = registry();
+        let mock = connect(&mut registry, Views::Mono(view(0, 0, 0, 0)));
+        let mut session = request_session(&mut registry, SessionMode::Inline).unwrap();
+        session.set_pixel_buffer(0);
+        for level in &[0.5, 2.0, f32::NAN] {
+            session.set_foveation_level(*level);
+            render(&mut registry, &mut session);
+        }
+        let (sender, receiver) = webxr_api::channel().unwrap();
+        mock.send(MockDeviceMsg::GetFoveationLevels(sender))
+            .unwrap();
+        assert_eq!(receiver.recv().unwrap(), vec![0.5, 1.0, 0.0]);
+    }
+
+    #[test]
+    fn captured_pixels_are_clipped_to_the_buffer() {
+        let mut registry = registry();
+        // The right eye's viewport hangs off the top right corner of the buffer.
+        let views = Views::Stereo(view(0, 0, 2, 2), view(3, 1, 2, 2));
+        let mock = connect(&mut registry, views);
+        let mut session = request_session(&mut registry, SessionMode::ImmersiveVR).unwrap();
+        session.set_pixel_buffer(0);
+        render(&mut registry, &mut session);
+        mock.send(MockDeviceMsg::CaptureFrames(1)).unwrap();
+        // Wait for the mock to start capturing.
+        let (sender, receiver) = webxr_api::channel().unwrap();
+        mock.send(MockDeviceMsg::GetCapturedFrames(sender.clone()))
+            .unwrap();
+        assert!(receiver.recv().unwrap().is_empty());
+        render(&mut registry, &mut session);
+        render(&mut registry, &mut session);
+
+        mock.send(MockDeviceMsg::GetCapturedFrames(sender)).unwrap();
+        let frames = receiver.recv().unwrap();
+        assert_eq!(frames.len(), 1);
+        let views = &frames[0].views;
+        assert_eq!(views.len(), 2);
+        assert_eq!(
+            views[0].viewport,
+            Rect::new(Point2D::new(0, 0), Size2D::new(2, 2))
+        );
+        #[rustfmt::skip]
+        assert_eq!(views[0].pixels, vec![
+            0, 0, 0, 255,  1, 0, 0, 255,
+            0, 1, 0, 255,  1, 1, 0, 255,
+        ]);
+        #[rustfmt::skip]
+        assert_eq!(views[1].pixels, vec![
+            3, 1, 0, 255,  0, 0, 0, 0,
+            0, 0, 0, 0,    0, 0, 0, 0,
+        ]);
+    }
+
+    #[test]
+    fn only_one_immersive_session_runs_at_a_time() {
+        let mut registry = registry();
+        let _mock = connect(&mut registry, Views::Mono(view(0, 0, 0, 0)));
+        let mut immersive = request_session(&mut registry, SessionMode::ImmersiveVR).unwrap();
+        let second = request_session(&mut registry, SessionMode::ImmersiveVR);
+        assert_eq!(second.err(), Some(Error::SessionAlreadyActive));
+        // Inline sessions can run alongside it.
+        let inline = request_session(&mut registry, SessionMode::Inline).unwrap();
+        drop(inline);
+        immersive.end_session();
+        while registry.running() {
+            registry.run_one_frame();
+        }
+        assert!(request_session(&mut registry, SessionMode::ImmersiveVR).is_ok());
+    }
+
+    #[test]
+    fn subscribers_are_told_when_devices_connect_and_disconnect() {
+        let mut registry = registry();
+        let (sender, changes) = webxr_api::channel().unwrap();
+        registry.registry().subscribe_device_changes(sender);
+        registry.run_one_frame();
+        let mock = connect(&mut registry, Views::Mono(view(0, 0, 0, 0)));
+        let id = match changes.recv().unwrap() {
+            DeviceChange::Connected(id) => id,
+            change => panic!("Expected a connection, got {:?}", change),
+        };
+        assert_eq!(registry.devices().len(), 1);
+
+        let (ack, receiver) = webxr_api::channel().unwrap();
+        mock.send(MockDeviceMsg::Disconnect(ack)).unwrap();
+        receiver.recv().unwrap();
+        registry.run_one_frame();
+        assert_eq!(changes.recv().unwrap(), DeviceChange::Disconnected(id));
+        assert!(registry.devices().is_empty());
+    }
+
+    #[test]
+    fn sessions_need_permission() {
+        let mut registry = registry();
+        let _mock = connect(&mut registry, Views::Mono(view(0, 0, 0, 0)));
+        let mut permissions = MockPermissions::default();
+        permissions.deny_sessions();
+        registry.set_permissions(permissions);
+        let denied = request_session(&mut registry, SessionMode::Inline);
+        assert_eq!(denied.err(), Some(Error::PermissionDenied));
+
+        let mut permissions = MockPermissions::default();
+        permissions.deny_feature("unbounded");
+        registry.set_permissions(permissions);
+        let init = SessionInit {
+            required_features: vec!["unbounded".into()],
+            optional_features: vec![],
+        };
+        let denied = request_session_with_init(&mut registry, SessionMode::Inline, init);
+        assert_eq!(denied.err(), Some(Error::PermissionDenied));
+        let init = SessionInit {
+            required_features: vec![],
+            optional_features: vec!["unbounded".into(), "local".into()],
+        };
+        let session = request_session_with_init(&mut registry, SessionMode::Inline, init).unwrap();
+        // Only features which were granted and which the device has are kept.
+        assert_eq!(
+            session.granted_features().to_vec(),
+            vec!["local".to_string()]
+        );
+    }
+
+    #[test]
+    fn dropped_permission_requests_are_denied() {
+        let mut registry = registry();
+        let _mock = connect(&mut registry, Views::Mono(view(0, 0, 0, 0)));
+        registry.set_permissions(IgnorePermissions);
+        let denied = request_session(&mut registry, SessionMode::Inline);
+        assert_eq!(denied.err(), Some(Error::PermissionDenied));
+    }
+
+    #[test]
+    fn permission_is_not_requested_for_sessions_which_cant_start() {
+        let mut registry = registry();
+        registry.set_permissions(UnreachablePermissions);
+        let unsupported = request_session(&mut registry, SessionMode::ImmersiveVR);
+        assert_eq!(
+            unsupported.err(),
+            Some(Error::UnsupportedMode(SessionMode::ImmersiveVR))
+        );
+
+        let _mock = connect(&mut registry, Views::Mono(view(0, 0, 0, 0)));
+        registry.set_permissions(MockPermissions::default());
+        let _immersive = request_session(&mut registry, SessionMode::ImmersiveVR).unwrap();
+        registry.set_permissions(UnreachablePermissions);
+        let second = request_session(&mut registry, SessionMode::ImmersiveVR);
+        assert_eq!(second.err(), Some(Error::SessionAlreadyActive));
+    }
+
+    #[test]
+    fn shutdown_ends_sessions_and_joins_their_threads() {
+        let mut registry = registry();
+        // Content still has the mock's sender, which mustn't stop it shutting down.
+        let _mock = connect(&mut registry, Views::Mono(view(0, 0, 0, 0)));
+        let mut headless = request_session(&mut registry, SessionMode::Inline).unwrap();
+        let (sender, events) = webxr_api::channel().unwrap();
+        headless.set_event_dest(sender);
+        registry.run_one_frame();
+
+        let dropped = Arc::new(AtomicBool::new(false));
+        let discovery = ThreadDiscovery {
+            dropped: dropped.clone(),
+        };
+        let id = registry.register(discovery).unwrap();
+        registry.set_preferred_device(id);
+        let _spawned = request_session(&mut registry, SessionMode::ImmersiveVR).unwrap();
+        assert!(!dropped.load(Ordering::SeqCst));
+
+        registry.shutdown();
+        assert!(dropped.load(Ordering::SeqCst));
+        assert!(!registry.running());
+        match events.recv().unwrap() {
+            Event::SessionEnd(EndReason::UserRequested) => (),
+            event => panic!("Expected the session to end, got {:?}", event),
+        }
+        assert!(registry.devices().is_empty());
+
+        let discovery = ThreadDiscovery {
+            dropped: Arc::new(AtomicBool::new(false)),
+        };
+        assert_eq!(registry.register(discovery), Err(Error::CommunicationError));
+        let session = request_session(&mut registry, SessionMode::Inline);
+        assert_eq!(session.err(), Some(Error::CommunicationError));
+    }
+}
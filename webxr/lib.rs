@@ -9,3 +9,18 @@ pub mod glwindow;
 
 #[cfg(feature = "headless")]
 pub mod headless;
+
+#[cfg(any(feature = "remote", feature = "scenario"))]
+mod pose;
+
+#[cfg(feature = "recording")]
+pub mod recording;
+
+#[cfg(feature = "remote")]
+pub mod remote;
+
+#[cfg(feature = "replay")]
+pub mod replay;
+
+#[cfg(feature = "scenario")]
+pub mod scenario;
@@ -0,0 +1,51 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use euclid::RigidTransform3D;
+use euclid::Rotation3D;
+use euclid::Vector3D;
+
+use serde::Deserialize;
+use serde::Serialize;
+
+/// A position in meters, and an orientation as an `[x, y, z, w]` quaternion.
+/// This is easier to write by hand than a serialized `RigidTransform3D`.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Pose {
+    #[serde(default)]
+    pub position: [f32; 3],
+    #[serde(default = "identity_orientation")]
+    pub orientation: [f32; 4],
+}
+
+fn identity_orientation() -> [f32; 4] {
+    [0.0, 0.0, 0.0, 1.0]
+}
+
+impl Default for Pose {
+    fn default() -> Pose {
+        Pose {
+            position: [0.0; 3],
+            orientation: identity_orientation(),
+        }
+    }
+}
+
+impl Pose {
+    pub fn transform<Src, Dst>(&self) -> RigidTransform3D<f32, Src, Dst> {
+        let [x, y, z, w] = self.orientation;
+        RigidTransform3D::new(
+            Rotation3D::unit_quaternion(x, y, z, w),
+            Vector3D::from(self.position),
+        )
+    }
+
+    pub fn from_transform<Src, Dst>(transform: &RigidTransform3D<f32, Src, Dst>) -> Pose {
+        let rotation = &transform.rotation;
+        Pose {
+            position: transform.translation.to_array(),
+            orientation: [rotation.i, rotation.j, rotation.k, rotation.r],
+        }
+    }
+}
@@ -0,0 +1,297 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Recording of sessions, for replaying them later.
+//!
+//! A trace is a file of JSON values, one per line. The first line is a `TraceHeader`,
+//! which describes the device, and each following line is a `TraceRecord`.
+
+use webxr_api::Device;
+use webxr_api::DeviceInfo;
+use webxr_api::DeviceNotifier;
+use webxr_api::Discovery;
+use webxr_api::EndReason;
+use webxr_api::Error;
+use webxr_api::Event;
+use webxr_api::Floor;
+use webxr_api::Frame;
+use webxr_api::InputSource;
+use webxr_api::Native;
+use webxr_api::PixelBuffer;
+use webxr_api::Quitter;
+use webxr_api::Sender;
+use webxr_api::Session;
+use webxr_api::SessionBuilder;
+use webxr_api::SessionMode;
+use webxr_api::Viewport;
+use webxr_api::Views;
+
+use euclid::default::Size2D as UntypedSize2D;
+use euclid::RigidTransform3D;
+use euclid::Size2D;
+
+use gleam::gl::GLsync;
+
+use log::warn;
+
+use serde::Deserialize;
+use serde::Serialize;
+
+use std::fs::File;
+use std::io;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Instant;
+
+/// The version of the trace format written by this crate.
+pub const TRACE_VERSION: u32 = 1;
+
+/// The first line of a trace.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TraceHeader {
+    pub version: u32,
+    pub mode: SessionMode,
+    pub floor_transform: RigidTransform3D<f32, Native, Floor>,
+    pub views: Views,
+    pub initial_inputs: Vec<InputSource>,
+}
+
+/// A line of a trace after the header. Each record has the time it happened,
+/// in milliseconds since the session started.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum TraceRecord {
+    Frame(f64, Frame),
+    Event(f64, Event),
+}
+
+/// A trace read back from a file.
+#[derive(Clone, Debug)]
+pub struct Trace {
+    pub header: TraceHeader,
+    pub records: Vec<TraceRecord>,
+}
+
+impl Trace {
+    pub fn read(path: &Path) -> io::Result<Trace> {
+        let mut lines = BufReader::new(File::open(path)?).lines();
+        let header: TraceHeader = match lines.next() {
+            Some(line) => serde_json::from_str(&line?)?,
+            None => return Err(io::Error::new(io::ErrorKind::InvalidData, "Empty trace")),
+        };
+        if header.version > TRACE_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unsupported trace version {}", header.version),
+            ));
+        }
+        let mut records = vec![];
+        for line in lines {
+            let line = line?;
+            if !line.trim().is_empty() {
+                records.push(serde_json::from_str(&line)?);
+            }
+        }
+        Ok(Trace { header, records })
+    }
+}
+
+/// A discovery which records every session of another discovery to a trace file.
+/// Each session overwrites the trace of the previous one.
+pub struct RecordingDiscovery<D> {
+    discovery: D,
+    path: PathBuf,
+}
+
+struct RecordingDevice {
+    device: Box<dyn Device>,
+    trace: Arc<Mutex<TraceWriter>>,
+}
+
+struct TraceWriter {
+    out: BufWriter<File>,
+    start: Instant,
+}
+
+impl<D: Discovery> RecordingDiscovery<D> {
+    pub fn new(discovery: D, path: PathBuf) -> RecordingDiscovery<D> {
+        RecordingDiscovery { discovery, path }
+    }
+}
+
+impl<D: Discovery> Discovery for RecordingDiscovery<D> {
+    fn request_session(&mut self, mode: SessionMode, xr: SessionBuilder) -> Result<Session, Error> {
+        let path = self.path.clone();
+        let xr = xr.wrap_device(move |device| {
+            let device = RecordingDevice::new(device, mode, path)?;
+            Ok(Box::new(device) as Box<dyn Device>)
+        });
+        self.discovery.request_session(mode, xr)
+    }
+
+    fn supports_session(&self, mode: SessionMode) -> bool {
+        self.discovery.supports_session(mode)
+    }
+
+    fn device_info(&self) -> DeviceInfo {
+        self.discovery.device_info()
+    }
+
+    fn set_device_notifier(&mut self, notifier: DeviceNotifier) {
+        self.discovery.set_device_notifier(notifier)
+    }
+
+    fn shutdown(&mut self) {
+        self.discovery.shutdown()
+    }
+}
+
+impl RecordingDevice {
+    fn new(
+        device: Box<dyn Device>,
+        mode: SessionMode,
+        path: PathBuf,
+    ) -> Result<RecordingDevice, Error> {
+        let file = File::create(&path).map_err(|err| {
+            Error::DeviceInitFailed(format!(
+                "Failed to create trace {}: {}",
+                path.display(),
+                err
+            ))
+        })?;
+        let mut trace = TraceWriter {
+            out: BufWriter::new(file),
+            start: Instant::now(),
+        };
+        trace.write(&TraceHeader {
+            version: TRACE_VERSION,
+            mode,
+            floor_transform: device.floor_transform(),
+            views: device.views(),
+            initial_inputs: device.initial_inputs(),
+        });
+        Ok(RecordingDevice {
+            device,
+            trace: Arc::new(Mutex::new(trace)),
+        })
+    }
+}
+
+impl Device for RecordingDevice {
+    fn floor_transform(&self) -> RigidTransform3D<f32, Native, Floor> {
+        self.device.floor_transform()
+    }
+
+    fn views(&self) -> Views {
+        self.device.views()
+    }
+
+    fn recommended_framebuffer_resolution(&self) -> Size2D<i32, Viewport> {
+        self.device.recommended_framebuffer_resolution()
+    }
+
+    fn wait_for_animation_frame(&mut self) -> Frame {
+        let frame = self.device.wait_for_animation_frame();
+        let mut trace = self.trace.lock().unwrap();
+        let record = TraceRecord::Frame(trace.time(), frame.clone());
+        trace.write(&record);
+        frame
+    }
+
+    fn render_animation_frame(
+        &mut self,
+        texture_id: u32,
+        size: UntypedSize2D<i32>,
+        sync: Option<GLsync>,
+    ) {
+        self.device.render_animation_frame(texture_id, size, sync)
+    }
+
+    fn supports_multiview(&self) -> bool {
+        self.device.supports_multiview()
+    }
+
+    fn render_multiview_animation_frame(
+        &mut self,
+        texture_id: u32,
+        size: UntypedSize2D<i32>,
+        sync: Option<GLsync>,
+    ) {
+        self.device
+            .render_multiview_animation_frame(texture_id, size, sync)
+    }
+
+    fn supports_pixel_buffers(&self) -> bool {
+        self.device.supports_pixel_buffers()
+    }
+
+    fn render_pixel_frame(&mut self, pixels: &PixelBuffer) {
+        self.device.render_pixel_frame(pixels)
+    }
+
+    fn supports_foveation(&self) -> bool {
+        self.device.supports_foveation()
+    }
+
+    fn set_foveation_level(&mut self, level: f32) {
+        self.device.set_foveation_level(level)
+    }
+
+    fn initial_inputs(&self) -> Vec<InputSource> {
+        self.device.initial_inputs()
+    }
+
+    fn set_event_dest(&mut self, dest: Sender<Event>) {
+        // Events are recorded by a thread which sits between the device and content,
+        // so they are forwarded as soon as they arrive rather than on the next frame.
+        let (sender, receiver) = match webxr_api::channel() {
+            Ok(channel) => channel,
+            Err(_) => {
+                warn!("Failed to create channel, events will not be recorded");
+                return self.device.set_event_dest(dest);
+            }
+        };
+        let trace = self.trace.clone();
+        thread::spawn(move || {
+            while let Ok(event) = receiver.recv() {
+                let mut trace = trace.lock().unwrap();
+                let record = TraceRecord::Event(trace.time(), event);
+                trace.write(&record);
+                let _ = dest.send(event);
+            }
+        });
+        self.device.set_event_dest(sender)
+    }
+
+    fn quit(&mut self, reason: EndReason) {
+        self.device.quit(reason)
+    }
+
+    fn set_quitter(&mut self, quitter: Quitter) {
+        self.device.set_quitter(quitter)
+    }
+}
+
+impl TraceWriter {
+    fn time(&self) -> f64 {
+        let elapsed = self.start.elapsed();
+        elapsed.as_secs() as f64 * 1000.0 + f64::from(elapsed.subsec_nanos()) / 1_000_000.0
+    }
+
+    // Each line is flushed, so the trace survives the process being killed.
+    fn write<T: Serialize>(&mut self, value: &T) {
+        let result = serde_json::to_writer(&mut self.out, value)
+            .map_err(Into::into)
+            .and_then(|()| writeln!(self.out))
+            .and_then(|()| self.out.flush());
+        if let Err(err) = result {
+            warn!("Failed to write trace: {}", err);
+        }
+    }
+}
@@ -0,0 +1,627 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Poses, inputs and events sent over the network, for driving a session
+//! from a phone's sensors or a separate tracking process.
+//!
+//! A `RemoteDiscovery` wraps another discovery, whose devices still render content,
+//! and listens for clients on a TCP socket. The viewer pose sent by a client replaces
+//! the device's, and its inputs and events are added to the device's.
+//!
+//! # Wire format
+//!
+//! Messages are JSON values, one per line. When a client connects, the server sends
+//! a `ServerMessage`, `{"Hello":{"version":1}}`. After that, the client sends
+//! `ClientMessage`s, and the server sends nothing more.
+//!
+//! Poses are `{"position":[x,y,z],"orientation":[x,y,z,w]}` in native coordinates,
+//! where either field can be left out. Input sources are
+//! `{"id":1,"handedness":"Left","target_ray_mode":"TrackedPointer"}`,
+//! and their ids must not clash with inputs of the wrapped device.
+//!
+//! ```json
+//! {"Viewer":{"position":[0,1.6,0]}}
+//! {"AddInput":{"id":1,"handedness":"Right","target_ray_mode":"TrackedPointer"}}
+//! {"Input":[1,{"position":[0.2,1.4,-0.3],"orientation":[0,0,0,1]}]}
+//! {"Select":[1,"Select","Start"]}
+//! {"Select":[1,"Select","Select"]}
+//! {"RemoveInput":1}
+//! {"Visibility":"VisibleBlurred"}
+//! "End"
+//! ```
+//!
+//! Inputs added by a client are removed when it disconnects.
+
+pub use crate::pose::Pose;
+
+use webxr_api::Device;
+use webxr_api::DeviceInfo;
+use webxr_api::DeviceNotifier;
+use webxr_api::Discovery;
+use webxr_api::EndReason;
+use webxr_api::Error;
+use webxr_api::Event;
+use webxr_api::EventBuffer;
+use webxr_api::Floor;
+use webxr_api::Frame;
+use webxr_api::Input;
+use webxr_api::InputFrame;
+use webxr_api::InputId;
+use webxr_api::InputSource;
+use webxr_api::Native;
+use webxr_api::PixelBuffer;
+use webxr_api::Quitter;
+use webxr_api::SelectEvent;
+use webxr_api::SelectKind;
+use webxr_api::Sender;
+use webxr_api::Session;
+use webxr_api::SessionBuilder;
+use webxr_api::SessionMode;
+use webxr_api::Viewer;
+use webxr_api::Viewport;
+use webxr_api::Views;
+use webxr_api::Visibility;
+
+use euclid::default::Size2D as UntypedSize2D;
+use euclid::RigidTransform3D;
+use euclid::Size2D;
+
+use gleam::gl::GLsync;
+
+use log::warn;
+
+use serde::Deserialize;
+use serde::Serialize;
+
+use std::io;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Write;
+use std::net::IpAddr;
+use std::net::Ipv4Addr;
+use std::net::Ipv6Addr;
+use std::net::Shutdown;
+use std::net::SocketAddr;
+use std::net::TcpListener;
+use std::net::TcpStream;
+use std::net::ToSocketAddrs;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::thread::JoinHandle;
+
+/// The version of the wire format spoken by this crate.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum ServerMessage {
+    Hello { version: u32 },
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum ClientMessage {
+    Viewer(Pose),
+    AddInput(InputSource),
+    Input(InputId, Pose),
+    RemoveInput(InputId),
+    Select(InputId, SelectKind, SelectEvent),
+    Visibility(Visibility),
+    /// End the current session
+    End,
+}
+
+/// A discovery whose devices are driven by clients connected over the network.
+/// It stops listening, and disconnects its clients, when the registry shuts down or it is dropped.
+pub struct RemoteDiscovery<D> {
+    discovery: D,
+    data: Arc<Mutex<RemoteData>>,
+    local_addr: SocketAddr,
+    listener: Option<Listener>,
+}
+
+// The thread accepting clients, and the threads reading from them.
+struct Listener {
+    stopped: Arc<AtomicBool>,
+    clients: Clients,
+    thread: JoinHandle<()>,
+}
+
+// Each client's stream is kept so it can be shut down, which ends its thread,
+// along with a flag the thread sets once it is about to finish.
+type Clients = Arc<Mutex<Vec<(TcpStream, JoinHandle<()>, Arc<AtomicBool>)>>>;
+
+struct RemoteDevice {
+    device: Box<dyn Device>,
+    data: Arc<Mutex<RemoteData>>,
+}
+
+struct RemoteInput {
+    source: InputSource,
+    pointer: Option<RigidTransform3D<f32, Input, Native>>,
+}
+
+struct RemoteData {
+    viewer: Option<RigidTransform3D<f32, Viewer, Native>>,
+    inputs: Vec<RemoteInput>,
+    events: EventBuffer,
+    quitter: Option<Quitter>,
+}
+
+/// A client of a `RemoteDiscovery`, for tools written in Rust,
+/// and for checking that messages make it through to the session.
+pub struct RemoteClient {
+    stream: TcpStream,
+}
+
+impl<D: Discovery> RemoteDiscovery<D> {
+    /// Listen for clients on the given address. Binding to port 0
+    /// picks a free port, which can be found with `local_addr`.
+    pub fn bind<A: ToSocketAddrs>(discovery: D, addr: A) -> io::Result<RemoteDiscovery<D>> {
+        let listener = TcpListener::bind(addr)?;
+        let local_addr = listener.local_addr()?;
+        let data = Arc::new(Mutex::new(RemoteData {
+            viewer: None,
+            inputs: vec![],
+            events: Default::default(),
+            quitter: None,
+        }));
+        let stopped = Arc::new(AtomicBool::new(false));
+        let clients: Clients = Default::default();
+        let data_ = data.clone();
+        let stopped_ = stopped.clone();
+        let clients_ = clients.clone();
+        let thread = thread::spawn(move || {
+            for stream in listener.incoming() {
+                if stopped_.load(Ordering::SeqCst) {
+                    break;
+                }
+                let (stream, clone) = match stream.and_then(|s| Ok((s.try_clone()?, s))) {
+                    Ok(streams) => streams,
+                    Err(err) => {
+                        warn!("Failed to accept remote client: {}", err);
+                        continue;
+                    }
+                };
+                let data = data_.clone();
+                let finished = Arc::new(AtomicBool::new(false));
+                let finished_ = finished.clone();
+                let handle = thread::spawn(move || {
+                    if let Err(err) = handle_client(stream, data) {
+                        warn!("Remote client failed: {}", err);
+                    }
+                    finished_.store(true, Ordering::SeqCst);
+                });
+                let mut clients = clients_.lock().unwrap();
+                clients.retain(|(_, _, finished)| !finished.load(Ordering::SeqCst));
+                clients.push((clone, handle, finished));
+            }
+        });
+        Ok(RemoteDiscovery {
+            discovery,
+            data,
+            local_addr,
+            listener: Some(Listener {
+                stopped,
+                clients,
+                thread,
+            }),
+        })
+    }
+}
+
+impl<D> RemoteDiscovery<D> {
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Stop accepting clients, disconnect the ones connected,
+    /// and wait for their threads to finish, which frees the port.
+    fn stop_listening(&mut self) {
+        let listener = match self.listener.take() {
+            Some(listener) => listener,
+            None => return,
+        };
+        listener.stopped.store(true, Ordering::SeqCst);
+        // Accepting blocks, so connect to wake the thread up. If that fails, the thread is
+        // left to stop when the next client connects, but the clients are still disconnected.
+        let mut addr = self.local_addr;
+        if addr.ip().is_unspecified() {
+            addr.set_ip(match addr.ip() {
+                IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::LOCALHOST),
+                IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::LOCALHOST),
+            });
+        }
+        match TcpStream::connect(addr) {
+            Ok(_) => {
+                let _ = listener.thread.join();
+            }
+            Err(err) => warn!("Failed to stop remote listener: {}", err),
+        }
+        let clients: Vec<_> = listener.clients.lock().unwrap().drain(..).collect();
+        for (stream, handle, _) in clients {
+            let _ = stream.shutdown(Shutdown::Both);
+            let _ = handle.join();
+        }
+    }
+}
+
+impl<D> Drop for RemoteDiscovery<D> {
+    fn drop(&mut self) {
+        self.stop_listening();
+    }
+}
+
+impl<D: Discovery> Discovery for RemoteDiscovery<D> {
+    fn request_session(&mut self, mode: SessionMode, xr: SessionBuilder) -> Result<Session, Error> {
+        let data = self.data.clone();
+        let xr = xr.wrap_device(move |device| {
+            data.lock().unwrap().events = Default::default();
+            Ok(Box::new(RemoteDevice { device, data }) as Box<dyn Device>)
+        });
+        self.discovery.request_session(mode, xr)
+    }
+
+    fn supports_session(&self, mode: SessionMode) -> bool {
+        self.discovery.supports_session(mode)
+    }
+
+    fn device_info(&self) -> DeviceInfo {
+        self.discovery.device_info()
+    }
+
+    fn set_device_notifier(&mut self, notifier: DeviceNotifier) {
+        self.discovery.set_device_notifier(notifier)
+    }
+
+    fn shutdown(&mut self) {
+        self.stop_listening();
+        self.discovery.shutdown()
+    }
+}
+
+fn handle_client(stream: TcpStream, data: Arc<Mutex<RemoteData>>) -> io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    let hello = ServerMessage::Hello {
+        version: PROTOCOL_VERSION,
+    };
+    serde_json::to_writer(&mut writer, &hello)?;
+    writeln!(writer)?;
+
+    let mut added = vec![];
+    let mut result = Ok(());
+    for line in BufReader::new(stream).lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(err) => {
+                result = Err(err);
+                break;
+            }
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let msg = match serde_json::from_str(&line) {
+            Ok(msg) => msg,
+            Err(err) => {
+                warn!("Ignoring bad remote message {:?}: {}", line, err);
+                continue;
+            }
+        };
+        let mut data = data.lock().unwrap();
+        match msg {
+            ClientMessage::AddInput(source) if !data.has_input(source.id) => added.push(source.id),
+            ClientMessage::RemoveInput(id) => added.retain(|added| *added != id),
+            _ => (),
+        }
+        data.handle_msg(msg);
+    }
+
+    let mut data = data.lock().unwrap();
+    for id in added {
+        data.handle_msg(ClientMessage::RemoveInput(id));
+    }
+    result
+}
+
+impl RemoteData {
+    fn has_input(&self, id: InputId) -> bool {
+        self.inputs.iter().any(|i| i.source.id == id)
+    }
+
+    fn handle_msg(&mut self, msg: ClientMessage) {
+        match msg {
+            ClientMessage::Viewer(pose) => self.viewer = Some(pose.transform()),
+            ClientMessage::AddInput(source) => {
+                if self.has_input(source.id) {
+                    warn!("Ignoring duplicate remote input {:?}", source.id);
+                    return;
+                }
+                self.inputs.push(RemoteInput {
+                    source,
+                    pointer: None,
+                });
+                self.events.callback(Event::AddInput(source));
+            }
+            ClientMessage::Input(id, pose) => {
+                if let Some(input) = self.inputs.iter_mut().find(|i| i.source.id == id) {
+                    input.pointer = Some(pose.transform());
+                }
+            }
+            ClientMessage::RemoveInput(id) => {
+                if self.has_input(id) {
+                    self.inputs.retain(|i| i.source.id != id);
+                    self.events.callback(Event::RemoveInput(id));
+                }
+            }
+            ClientMessage::Select(id, kind, event) => {
+                self.events.callback(Event::Select(id, kind, event));
+            }
+            ClientMessage::Visibility(visibility) => {
+                self.events.callback(Event::VisibilityChange(visibility));
+            }
+            ClientMessage::End => {
+                if let Some(ref quitter) = self.quitter {
+                    quitter.quit(EndReason::UserRequested);
+                }
+            }
+        }
+    }
+}
+
+impl Device for RemoteDevice {
+    fn floor_transform(&self) -> RigidTransform3D<f32, Native, Floor> {
+        self.device.floor_transform()
+    }
+
+    fn views(&self) -> Views {
+        self.device.views()
+    }
+
+    fn recommended_framebuffer_resolution(&self) -> Size2D<i32, Viewport> {
+        self.device.recommended_framebuffer_resolution()
+    }
+
+    fn wait_for_animation_frame(&mut self) -> Frame {
+        let mut frame = self.device.wait_for_animation_frame();
+        let data = self.data.lock().unwrap();
+        if let Some(viewer) = data.viewer {
+            frame.transform = viewer;
+        }
+        frame.inputs.extend(data.inputs.iter().filter_map(|input| {
+            Some(InputFrame {
+                id: input.source.id,
+                target_ray_origin: input.pointer?,
+            })
+        }));
+        frame
+    }
+
+    fn render_animation_frame(
+        &mut self,
+        texture_id: u32,
+        size: UntypedSize2D<i32>,
+        sync: Option<GLsync>,
+    ) {
+        self.device.render_animation_frame(texture_id, size, sync)
+    }
+
+    fn supports_multiview(&self) -> bool {
+        self.device.supports_multiview()
+    }
+
+    fn render_multiview_animation_frame(
+        &mut self,
+        texture_id: u32,
+        size: UntypedSize2D<i32>,
+        sync: Option<GLsync>,
+    ) {
+        self.device
+            .render_multiview_animation_frame(texture_id, size, sync)
+    }
+
+    fn supports_pixel_buffers(&self) -> bool {
+        self.device.supports_pixel_buffers()
+    }
+
+    fn render_pixel_frame(&mut self, pixels: &PixelBuffer) {
+        self.device.render_pixel_frame(pixels)
+    }
+
+    fn supports_foveation(&self) -> bool {
+        self.device.supports_foveation()
+    }
+
+    fn set_foveation_level(&mut self, level: f32) {
+        self.device.set_foveation_level(level)
+    }
+
+    fn initial_inputs(&self) -> Vec<InputSource> {
+        let mut inputs = self.device.initial_inputs();
+        let data = self.data.lock().unwrap();
+        inputs.extend(data.inputs.iter().map(|input| input.source));
+        inputs
+    }
+
+    fn set_event_dest(&mut self, dest: Sender<Event>) {
+        self.data.lock().unwrap().events.upgrade(dest.clone());
+        self.device.set_event_dest(dest)
+    }
+
+    fn quit(&mut self, reason: EndReason) {
+        self.device.quit(reason)
+    }
+
+    fn set_quitter(&mut self, quitter: Quitter) {
+        self.data.lock().unwrap().quitter = Some(quitter.clone());
+        self.device.set_quitter(quitter)
+    }
+}
+
+impl Drop for RemoteDevice {
+    fn drop(&mut self) {
+        self.data.lock().unwrap().quitter = None;
+    }
+}
+
+impl RemoteClient {
+    /// Connect to a `RemoteDiscovery`, and check that it speaks our version of the wire format.
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> io::Result<RemoteClient> {
+        let stream = TcpStream::connect(addr)?;
+        let mut hello = String::new();
+        BufReader::new(&stream).read_line(&mut hello)?;
+        match serde_json::from_str(&hello)? {
+            ServerMessage::Hello {
+                version: PROTOCOL_VERSION,
+            } => Ok(RemoteClient { stream }),
+            ServerMessage::Hello { version } => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unsupported protocol version {}", version),
+            )),
+        }
+    }
+
+    pub fn send(&mut self, msg: &ClientMessage) -> io::Result<()> {
+        let mut line = serde_json::to_vec(msg)?;
+        line.push(b'\n');
+        self.stream.write_all(&line)
+    }
+}
+
+#[cfg(all(test, feature = "headless"))]
+mod tests {
+    use super::ClientMessage;
+    use super::Pose;
+    use super::RemoteClient;
+    use super::RemoteDiscovery;
+
+    use crate::headless::HeadlessMockDiscovery;
+
+    use webxr_api::Event;
+    use webxr_api::Handedness;
+    use webxr_api::InputId;
+    use webxr_api::InputSource;
+    use webxr_api::MainThreadRegistry;
+    use webxr_api::MainThreadWaker;
+    use webxr_api::MockDeviceInit;
+    use webxr_api::MockDiscovery;
+    use webxr_api::PixelBuffer;
+    use webxr_api::PixelBufferApi;
+    use webxr_api::PixelBufferId;
+    use webxr_api::SelectEvent;
+    use webxr_api::SelectKind;
+    use webxr_api::SessionMode;
+    use webxr_api::TargetRayMode;
+    use webxr_api::View;
+    use webxr_api::Views;
+
+    use euclid::Rect;
+    use euclid::RigidTransform3D;
+    use euclid::Transform3D;
+    use euclid::Vector3D;
+
+    struct Waker;
+
+    impl MainThreadWaker for Waker {
+        fn clone_box(&self) -> Box<dyn MainThreadWaker> {
+            Box::new(Waker)
+        }
+        fn wake(&self) {}
+    }
+
+    struct NoPixels;
+
+    impl PixelBufferApi for NoPixels {
+        fn lock(&self, _: PixelBufferId) -> Option<PixelBuffer> {
+            None
+        }
+        fn unlock(&self, _: PixelBufferId) {}
+        fn clone_box(&self) -> Box<dyn PixelBufferApi> {
+            Box::new(NoPixels)
+        }
+    }
+
+    #[test]
+    fn loopback() {
+        let mut registry = MainThreadRegistry::new(Box::new(Waker)).unwrap();
+        registry.set_pixel_buffers(Box::new(NoPixels));
+        let init = MockDeviceInit {
+            floor_origin: RigidTransform3D::identity(),
+            supports_immersive: false,
+            supports_unbounded: false,
+            viewer_origin: RigidTransform3D::identity(),
+            views: Views::Mono(View {
+                transform: RigidTransform3D::identity(),
+                projection: Transform3D::identity(),
+                viewport: Rect::zero(),
+            }),
+        };
+        let (mock, receiver) = webxr_api::channel().unwrap();
+        let headless = HeadlessMockDiscovery::without_gl()
+            .simulate_device_connection(init, receiver)
+            .unwrap();
+        let remote = RemoteDiscovery::bind(headless, "127.0.0.1:0").unwrap();
+        let addr = remote.local_addr();
+        registry.register(remote).unwrap();
+
+        let (sender, receiver) = webxr_api::channel().unwrap();
+        let init = Default::default();
+        registry
+            .registry()
+            .request_session(SessionMode::Inline, init, sender);
+        registry.run_one_frame();
+        let mut session = receiver.recv().unwrap().unwrap();
+        let (sender, events) = webxr_api::channel().unwrap();
+        session.set_event_dest(sender);
+        registry.run_one_frame();
+
+        let id = InputId(1);
+        let source = InputSource {
+            handedness: Handedness::Right,
+            target_ray_mode: TargetRayMode::TrackedPointer,
+            id,
+        };
+        let viewer = Pose {
+            position: [0.0, 1.6, 0.0],
+            orientation: [0.0, 0.0, 0.0, 1.0],
+        };
+        let pointer = Pose {
+            position: [0.2, 1.4, -0.3],
+            orientation: [0.0, 0.0, 0.0, 1.0],
+        };
+        let mut client = RemoteClient::connect(addr).unwrap();
+        client.send(&ClientMessage::Viewer(viewer)).unwrap();
+        client.send(&ClientMessage::AddInput(source)).unwrap();
+        client.send(&ClientMessage::Input(id, pointer)).unwrap();
+        let select = ClientMessage::Select(id, SelectKind::Select, SelectEvent::Start);
+        client.send(&select).unwrap();
+
+        // Messages are handled in order, so the poses have arrived once the select has.
+        match events.recv().unwrap() {
+            Event::AddInput(added) => assert_eq!(added.id, id),
+            event => panic!("Unexpected event {:?}", event),
+        }
+        match events.recv().unwrap() {
+            Event::Select(selected, SelectKind::Select, SelectEvent::Start) => {
+                assert_eq!(selected, id)
+            }
+            event => panic!("Unexpected event {:?}", event),
+        }
+
+        let (sender, frames) = webxr_api::channel().unwrap();
+        session.request_animation_frame(sender);
+        registry.run_one_frame();
+        let (_, frame) = frames.recv().unwrap();
+        assert_eq!(frame.transform.translation, Vector3D::new(0.0, 1.6, 0.0));
+        assert_eq!(frame.inputs.len(), 1);
+        assert_eq!(frame.inputs[0].id, id);
+        let translation = frame.inputs[0].target_ray_origin.translation;
+        assert_eq!(translation, Vector3D::new(0.2, 1.4, -0.3));
+
+        // Shutting down disconnects the client and frees the port.
+        drop(mock);
+        registry.shutdown();
+        assert!(RemoteClient::connect(addr).is_err());
+    }
+}
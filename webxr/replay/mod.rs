@@ -0,0 +1,469 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Playback of recorded traces, for reproducing sessions on machines without a device.
+//!
+//! Rendered frames are discarded, so content can use either GL textures or pixel buffers.
+
+use crate::recording::Trace;
+use crate::recording::TraceRecord;
+
+use webxr_api::Device;
+use webxr_api::DeviceInfo;
+use webxr_api::Discovery;
+use webxr_api::EndReason;
+use webxr_api::Error;
+use webxr_api::Event;
+use webxr_api::EventBuffer;
+use webxr_api::Floor;
+use webxr_api::Frame;
+use webxr_api::InputSource;
+use webxr_api::Native;
+use webxr_api::Quitter;
+use webxr_api::Sender;
+use webxr_api::Session;
+use webxr_api::SessionBuilder;
+use webxr_api::SessionMode;
+use webxr_api::Views;
+
+use euclid::default::Size2D;
+use euclid::RigidTransform3D;
+
+use gleam::gl::GLsync;
+use gleam::gl::GLuint;
+
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+/// How fast a trace is played back.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ReplayPacing {
+    /// Frames are returned at the times they were recorded.
+    RealTime,
+    /// Frames are returned as soon as they are requested.
+    AsFastAsPossible,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ReplayOptions {
+    pub pacing: ReplayPacing,
+    /// Whether to start again from the seek point at the end of the trace,
+    /// rather than ending the session.
+    pub looping: bool,
+    /// The time in the trace to start playing from, in milliseconds.
+    pub seek: f64,
+}
+
+impl Default for ReplayOptions {
+    fn default() -> ReplayOptions {
+        ReplayOptions {
+            pacing: ReplayPacing::RealTime,
+            looping: false,
+            seek: 0.0,
+        }
+    }
+}
+
+/// A discovery whose sessions play back a trace written by a `RecordingDiscovery`.
+pub struct ReplayDiscovery {
+    trace: Arc<Trace>,
+    options: ReplayOptions,
+}
+
+struct ReplayDevice {
+    trace: Arc<Trace>,
+    options: ReplayOptions,
+    // The index of the first record after the seek point,
+    // and the inputs which were connected at that point.
+    start_index: usize,
+    start_inputs: Vec<InputSource>,
+    // Whether there are any frames after the seek point, so looping makes progress.
+    looping: bool,
+    index: usize,
+    inputs: Vec<InputSource>,
+    // When playback reached the seek point.
+    clock: Instant,
+    frame: Frame,
+    events: EventBuffer,
+    quitter: Option<Quitter>,
+    ended: bool,
+}
+
+impl ReplayDiscovery {
+    pub fn new(trace: Trace, options: ReplayOptions) -> ReplayDiscovery {
+        ReplayDiscovery {
+            trace: Arc::new(trace),
+            options,
+        }
+    }
+}
+
+impl Discovery for ReplayDiscovery {
+    fn request_session(&mut self, mode: SessionMode, xr: SessionBuilder) -> Result<Session, Error> {
+        if !self.supports_session(mode) {
+            return Err(Error::UnsupportedMode(mode));
+        }
+        let trace = self.trace.clone();
+        let options = self.options;
+        xr.spawn(move || Ok(ReplayDevice::new(trace, options)))
+    }
+
+    fn supports_session(&self, mode: SessionMode) -> bool {
+        mode == self.trace.header.mode
+    }
+
+    fn device_info(&self) -> DeviceInfo {
+        DeviceInfo::new("Trace replay", "webxr", self)
+    }
+}
+
+impl ReplayDevice {
+    fn new(trace: Arc<Trace>, options: ReplayOptions) -> ReplayDevice {
+        // Seeking skips the records before the seek point, but keeps track
+        // of the inputs they connect and the most recent frame.
+        let mut inputs = trace.header.initial_inputs.clone();
+        let mut frame = None;
+        let mut start_index = trace.records.len();
+        for (index, record) in trace.records.iter().enumerate() {
+            match *record {
+                TraceRecord::Frame(time, _) | TraceRecord::Event(time, _)
+                    if time >= options.seek =>
+                {
+                    start_index = index;
+                    break;
+                }
+                TraceRecord::Frame(_, ref recorded) => frame = Some(recorded.clone()),
+                TraceRecord::Event(_, event) => update_inputs(&mut inputs, event),
+            }
+        }
+        let later_frames = trace.records[start_index..]
+            .iter()
+            .filter_map(|record| match *record {
+                TraceRecord::Frame(_, ref frame) => Some(frame.clone()),
+                TraceRecord::Event(..) => None,
+            });
+        let mut later_frames = later_frames.peekable();
+        let looping = options.looping && later_frames.peek().is_some();
+        let frame = frame
+            .or_else(|| later_frames.next())
+            .unwrap_or_else(|| Frame {
+                transform: RigidTransform3D::identity(),
+                inputs: vec![],
+            });
+        ReplayDevice {
+            trace,
+            options,
+            start_index,
+            start_inputs: inputs.clone(),
+            looping,
+            index: start_index,
+            inputs,
+            clock: Instant::now(),
+            frame,
+            events: Default::default(),
+            quitter: None,
+            ended: false,
+        }
+    }
+
+    fn wait_until(&self, time: f64) {
+        if self.options.pacing == ReplayPacing::AsFastAsPossible {
+            return;
+        }
+        let target = Duration::from_micros(((time - self.options.seek).max(0.0) * 1000.0) as u64);
+        let elapsed = self.clock.elapsed();
+        if target > elapsed {
+            thread::sleep(target - elapsed);
+        }
+    }
+
+    fn dispatch(&mut self, event: Event) {
+        // The session is ended by the end of the trace, not when it was recorded.
+        if let Event::SessionEnd(_) = event {
+            return;
+        }
+        update_inputs(&mut self.inputs, event);
+        self.events.callback(event);
+    }
+
+    // Go back to the seek point, reconnecting the inputs which were connected then.
+    fn restart(&mut self) {
+        for input in &self.inputs {
+            if !self.start_inputs.iter().any(|i| i.id == input.id) {
+                self.events.callback(Event::RemoveInput(input.id));
+            }
+        }
+        for input in &self.start_inputs {
+            if !self.inputs.iter().any(|i| i.id == input.id) {
+                self.events.callback(Event::AddInput(*input));
+            }
+        }
+        self.inputs = self.start_inputs.clone();
+        self.index = self.start_index;
+        self.clock = Instant::now();
+    }
+
+    fn end(&mut self) {
+        if self.ended {
+            return;
+        }
+        self.ended = true;
+        if let Some(ref quitter) = self.quitter {
+            quitter.quit(EndReason::DeviceInitiated);
+        }
+    }
+}
+
+impl Device for ReplayDevice {
+    fn floor_transform(&self) -> RigidTransform3D<f32, Native, Floor> {
+        self.trace.header.floor_transform
+    }
+
+    fn views(&self) -> Views {
+        self.trace.header.views.clone()
+    }
+
+    fn wait_for_animation_frame(&mut self) -> Frame {
+        loop {
+            let trace = self.trace.clone();
+            match trace.records.get(self.index) {
+                None if self.looping => self.restart(),
+                None => {
+                    // Keep returning the last frame until the session quits.
+                    self.end();
+                    return self.frame.clone();
+                }
+                Some(&TraceRecord::Event(time, event)) => {
+                    self.index += 1;
+                    self.wait_until(time);
+                    self.dispatch(event);
+                }
+                Some(&TraceRecord::Frame(time, ref frame)) => {
+                    self.index += 1;
+                    self.wait_until(time);
+                    self.frame = frame.clone();
+                    return self.frame.clone();
+                }
+            }
+        }
+    }
+
+    fn render_animation_frame(&mut self, _: GLuint, _: Size2D<i32>, _: Option<GLsync>) {}
+
+    fn supports_pixel_buffers(&self) -> bool {
+        true
+    }
+
+    fn initial_inputs(&self) -> Vec<InputSource> {
+        self.start_inputs.clone()
+    }
+
+    fn set_event_dest(&mut self, dest: Sender<Event>) {
+        self.events.upgrade(dest)
+    }
+
+    fn quit(&mut self, reason: EndReason) {
+        self.events.callback(Event::SessionEnd(reason));
+    }
+
+    fn set_quitter(&mut self, quitter: Quitter) {
+        self.quitter = Some(quitter);
+    }
+}
+
+fn update_inputs(inputs: &mut Vec<InputSource>, event: Event) {
+    match event {
+        Event::AddInput(source) => inputs.push(source),
+        Event::RemoveInput(id) => inputs.retain(|i| i.id != id),
+        _ => (),
+    }
+}
+
+#[cfg(all(test, feature = "headless"))]
+mod tests {
+    use super::ReplayDiscovery;
+    use super::ReplayOptions;
+    use super::ReplayPacing;
+
+    use crate::headless::HeadlessMockDiscovery;
+    use crate::recording::RecordingDiscovery;
+    use crate::recording::Trace;
+
+    use webxr_api::Discovery;
+    use webxr_api::EndReason;
+    use webxr_api::Event;
+    use webxr_api::Frame;
+    use webxr_api::Handedness;
+    use webxr_api::InputId;
+    use webxr_api::InputSource;
+    use webxr_api::MainThreadRegistry;
+    use webxr_api::MainThreadWaker;
+    use webxr_api::MockDeviceInit;
+    use webxr_api::MockDeviceMsg;
+    use webxr_api::MockDiscovery;
+    use webxr_api::MockInputInit;
+    use webxr_api::MockInputMsg;
+    use webxr_api::PixelBuffer;
+    use webxr_api::PixelBufferApi;
+    use webxr_api::PixelBufferId;
+    use webxr_api::Receiver;
+    use webxr_api::SelectEvent;
+    use webxr_api::SelectKind;
+    use webxr_api::Session;
+    use webxr_api::SessionMode;
+    use webxr_api::TargetRayMode;
+    use webxr_api::View;
+    use webxr_api::Views;
+
+    use euclid::Rect;
+    use euclid::RigidTransform3D;
+    use euclid::Transform3D;
+    use euclid::Vector3D;
+
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    struct Waker;
+
+    impl MainThreadWaker for Waker {
+        fn clone_box(&self) -> Box<dyn MainThreadWaker> {
+            Box::new(Waker)
+        }
+        fn wake(&self) {}
+    }
+
+    struct NoPixels;
+
+    impl PixelBufferApi for NoPixels {
+        fn lock(&self, _: PixelBufferId) -> Option<PixelBuffer> {
+            None
+        }
+        fn unlock(&self, _: PixelBufferId) {}
+        fn clone_box(&self) -> Box<dyn PixelBufferApi> {
+            Box::new(NoPixels)
+        }
+    }
+
+    fn start_session<D: Discovery>(discovery: D) -> (MainThreadRegistry, Session, Receiver<Event>) {
+        let mut registry = MainThreadRegistry::new(Box::new(Waker)).unwrap();
+        registry.set_pixel_buffers(Box::new(NoPixels));
+        registry.register(discovery).unwrap();
+        let (sender, receiver) = webxr_api::channel().unwrap();
+        let init = Default::default();
+        registry
+            .registry()
+            .request_session(SessionMode::ImmersiveVR, init, sender);
+        registry.run_one_frame();
+        let mut session = receiver.recv().unwrap().unwrap();
+        let (sender, events) = webxr_api::channel().unwrap();
+        session.set_event_dest(sender);
+        (registry, session, events)
+    }
+
+    fn frame(registry: &mut MainThreadRegistry, session: &mut Session) -> Frame {
+        let (sender, receiver) = webxr_api::channel().unwrap();
+        session.request_animation_frame(sender);
+        registry.run_one_frame();
+        receiver.recv().unwrap().1
+    }
+
+    fn x(frame: &Frame) -> f32 {
+        frame.transform.translation.x
+    }
+
+    fn viewer(x: f32) -> MockDeviceMsg {
+        let translation = Vector3D::new(x, 0.0, 0.0);
+        MockDeviceMsg::SetViewerOrigin(RigidTransform3D::from_translation(translation))
+    }
+
+    #[test]
+    fn replay_matches_recording() {
+        let path = env::temp_dir().join(format!("webxr-replay-test-{}.trace", process::id()));
+        let init = MockDeviceInit {
+            floor_origin: RigidTransform3D::identity(),
+            supports_immersive: true,
+            supports_unbounded: false,
+            viewer_origin: RigidTransform3D::identity(),
+            views: Views::Mono(View {
+                transform: RigidTransform3D::identity(),
+                projection: Transform3D::identity(),
+                viewport: Rect::zero(),
+            }),
+        };
+        let (mock, receiver) = webxr_api::channel().unwrap();
+        let headless = HeadlessMockDiscovery::without_gl()
+            .simulate_device_connection(init, receiver)
+            .unwrap();
+        let recording = RecordingDiscovery::new(headless, path.clone());
+        let (mut registry, mut session, events) = start_session(recording);
+        registry.run_one_frame();
+
+        let id = InputId(1);
+        let source = InputSource {
+            handedness: Handedness::Left,
+            target_ray_mode: TargetRayMode::TrackedPointer,
+            id,
+        };
+        let pointer_origin = RigidTransform3D::identity();
+        let select = MockInputMsg::TriggerSelect(SelectKind::Select, SelectEvent::Select);
+
+        // The mock handles messages in order, so each event is sent after the pose before it.
+        mock.send(viewer(1.0)).unwrap();
+        let add = MockDeviceMsg::AddInputSource(MockInputInit {
+            source,
+            pointer_origin,
+        });
+        mock.send(add).unwrap();
+        events.recv().unwrap();
+        assert_eq!(x(&frame(&mut registry, &mut session)), 1.0);
+        mock.send(viewer(2.0)).unwrap();
+        mock.send(MockDeviceMsg::MessageInputSource(id, select))
+            .unwrap();
+        events.recv().unwrap();
+        assert_eq!(x(&frame(&mut registry, &mut session)), 2.0);
+        session.end_session();
+        registry.run_one_frame();
+        match events.recv().unwrap() {
+            Event::SessionEnd(EndReason::UserRequested) => (),
+            event => panic!("Unexpected event {:?}", event),
+        }
+        drop(mock);
+        registry.shutdown();
+
+        let trace = Trace::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let options = ReplayOptions {
+            pacing: ReplayPacing::AsFastAsPossible,
+            ..Default::default()
+        };
+        let (mut registry, mut session, events) =
+            start_session(ReplayDiscovery::new(trace, options));
+        let first = frame(&mut registry, &mut session);
+        assert_eq!(x(&first), 1.0);
+        assert_eq!(first.inputs.len(), 1);
+        assert_eq!(first.inputs[0].id, id);
+        assert_eq!(x(&frame(&mut registry, &mut session)), 2.0);
+        match events.recv().unwrap() {
+            Event::AddInput(added) => assert_eq!(added.id, id),
+            event => panic!("Unexpected event {:?}", event),
+        }
+        match events.recv().unwrap() {
+            Event::Select(selected, SelectKind::Select, SelectEvent::Select) => {
+                assert_eq!(selected, id)
+            }
+            event => panic!("Unexpected event {:?}", event),
+        }
+
+        // The replayed session ends with the trace, rather than when the recording did.
+        frame(&mut registry, &mut session);
+        match events.recv().unwrap() {
+            Event::SessionEnd(EndReason::DeviceInitiated) => (),
+            event => panic!("Unexpected event {:?}", event),
+        }
+        registry.shutdown();
+    }
+}
@@ -0,0 +1,100 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! The device of a scenario, which is written with poses like the keyframes are,
+//! and converted to a `MockDeviceInit` when it is loaded.
+
+use crate::pose::Pose;
+
+use webxr_api::MockDeviceInit;
+use webxr_api::View;
+use webxr_api::Viewport;
+use webxr_api::Views;
+
+use euclid::Rect;
+use euclid::Transform3D;
+
+use serde::Deserialize;
+use serde::Deserializer;
+use serde::Serialize;
+use serde::Serializer;
+
+#[derive(Serialize, Deserialize)]
+struct DeviceInit {
+    #[serde(default)]
+    floor_origin: Pose,
+    #[serde(default)]
+    viewer_origin: Pose,
+    #[serde(default)]
+    supports_immersive: bool,
+    #[serde(default)]
+    supports_unbounded: bool,
+    views: ViewsInit,
+}
+
+#[derive(Serialize, Deserialize)]
+enum ViewsInit {
+    Mono(ViewInit),
+    Stereo(ViewInit, ViewInit),
+}
+
+/// A view, whose transform is from the viewer to the eye,
+/// and whose projection is a 4x4 matrix in row-major order.
+#[derive(Serialize, Deserialize)]
+struct ViewInit {
+    #[serde(default)]
+    transform: Pose,
+    projection: [f32; 16],
+    viewport: Rect<i32, Viewport>,
+}
+
+pub fn serialize<S: Serializer>(init: &MockDeviceInit, serializer: S) -> Result<S::Ok, S::Error> {
+    let views = match init.views {
+        Views::Mono(ref view) => ViewsInit::Mono(ViewInit::new(view)),
+        Views::Stereo(ref left, ref right) => {
+            ViewsInit::Stereo(ViewInit::new(left), ViewInit::new(right))
+        }
+    };
+    let init = DeviceInit {
+        floor_origin: Pose::from_transform(&init.floor_origin),
+        viewer_origin: Pose::from_transform(&init.viewer_origin),
+        supports_immersive: init.supports_immersive,
+        supports_unbounded: init.supports_unbounded,
+        views,
+    };
+    init.serialize(serializer)
+}
+
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<MockDeviceInit, D::Error> {
+    let init = DeviceInit::deserialize(deserializer)?;
+    let views = match init.views {
+        ViewsInit::Mono(view) => Views::Mono(view.view()),
+        ViewsInit::Stereo(left, right) => Views::Stereo(left.view(), right.view()),
+    };
+    Ok(MockDeviceInit {
+        floor_origin: init.floor_origin.transform(),
+        viewer_origin: init.viewer_origin.transform(),
+        supports_immersive: init.supports_immersive,
+        supports_unbounded: init.supports_unbounded,
+        views,
+    })
+}
+
+impl ViewInit {
+    fn new<Eye>(view: &View<Eye>) -> ViewInit {
+        ViewInit {
+            transform: Pose::from_transform(&view.transform),
+            projection: view.projection.to_row_major_array(),
+            viewport: view.viewport,
+        }
+    }
+
+    fn view<Eye>(&self) -> View<Eye> {
+        View {
+            transform: self.transform.transform(),
+            projection: Transform3D::from_array(self.projection),
+            viewport: self.viewport,
+        }
+    }
+}
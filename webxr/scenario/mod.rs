@@ -0,0 +1,386 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Scripted mock devices, for tests which would otherwise send `MockDeviceMsg`s by hand.
+//!
+//! A scenario is a JSON file describing the device, keyframes for the poses
+//! of the viewer and inputs, and timed events. The device is written like
+//! a `MockDeviceInit`, but with poses in place of transforms.
+//! Times are in milliseconds, keyframes are in time order,
+//! and poses are interpolated between keyframes.
+//!
+//! A `ScenarioRunner` feeds a scenario to a mock device, such as one from
+//! `HeadlessMockDiscovery`, which was connected with the scenario's `device`.
+
+pub use crate::pose::Pose;
+
+mod device;
+
+use webxr_api::InputId;
+use webxr_api::InputSource;
+use webxr_api::MockDeviceInit;
+use webxr_api::MockDeviceMsg;
+use webxr_api::MockInputInit;
+use webxr_api::MockInputMsg;
+use webxr_api::Native;
+use webxr_api::SelectEvent;
+use webxr_api::SelectKind;
+use webxr_api::Sender;
+
+use euclid::RigidTransform3D;
+
+use serde::Deserialize;
+use serde::Serialize;
+
+use std::cmp::Ordering;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Scenario {
+    #[serde(with = "device")]
+    pub device: MockDeviceInit,
+    #[serde(default)]
+    pub viewer: Vec<Keyframe>,
+    #[serde(default)]
+    pub inputs: Vec<ScenarioInput>,
+    #[serde(default)]
+    pub events: Vec<TimedEvent>,
+}
+
+/// The pose of the viewer or an input at a given time.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Keyframe {
+    pub time: f64,
+    pub pose: Pose,
+}
+
+/// An input source, which is added at the time of its first keyframe.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ScenarioInput {
+    pub source: InputSource,
+    #[serde(default)]
+    pub keyframes: Vec<Keyframe>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TimedEvent {
+    pub time: f64,
+    pub event: ScenarioEvent,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum ScenarioEvent {
+    Select(InputId, SelectKind, SelectEvent),
+    DisconnectInput(InputId),
+    ReconnectInput(InputId),
+    Focus,
+    Blur,
+    /// Disconnect the device, which ends the scenario
+    Disconnect,
+}
+
+impl Scenario {
+    /// Parse a scenario, checking that its times are all numbers.
+    pub fn from_json(json: &str) -> io::Result<Scenario> {
+        let scenario: Scenario = serde_json::from_str(json)?;
+        if !scenario.times().all(f64::is_finite) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Scenario times must be finite numbers",
+            ));
+        }
+        Ok(scenario)
+    }
+
+    pub fn read(path: &Path) -> io::Result<Scenario> {
+        Scenario::from_json(&fs::read_to_string(path)?)
+    }
+
+    /// The time of the last keyframe or event.
+    pub fn duration(&self) -> f64 {
+        self.times().fold(0.0, f64::max)
+    }
+
+    fn times(&self) -> impl Iterator<Item = f64> + '_ {
+        let viewer = self.viewer.iter().map(|k| k.time);
+        let inputs = self
+            .inputs
+            .iter()
+            .flat_map(|i| i.keyframes.iter().map(|k| k.time));
+        let events = self.events.iter().map(|e| e.time);
+        viewer.chain(inputs).chain(events)
+    }
+}
+
+pub struct ScenarioRunner {
+    scenario: Scenario,
+    sender: Sender<MockDeviceMsg>,
+    time: f64,
+    next_event: usize,
+    added: Vec<bool>,
+    disconnected: bool,
+}
+
+impl ScenarioRunner {
+    pub fn new(mut scenario: Scenario, sender: Sender<MockDeviceMsg>) -> ScenarioRunner {
+        // Scenarios with times which aren't numbers are rejected when they are parsed.
+        scenario
+            .events
+            .sort_by(|a, b| a.time.partial_cmp(&b.time).unwrap_or(Ordering::Equal));
+        let added = vec![false; scenario.inputs.len()];
+        ScenarioRunner {
+            scenario,
+            sender,
+            time: 0.0,
+            next_event: 0,
+            added,
+            disconnected: false,
+        }
+    }
+
+    /// The time that the scenario has been run up to.
+    pub fn time(&self) -> f64 {
+        self.time
+    }
+
+    /// Whether the scenario has finished, either by reaching its end
+    /// or by disconnecting the device.
+    pub fn finished(&self) -> bool {
+        self.disconnected || self.time >= self.scenario.duration()
+    }
+
+    /// Run the scenario up to the given time, by sending the poses at that time,
+    /// and any events since the last call. This gives tests full control over timing.
+    pub fn advance_to(&mut self, time: f64) {
+        if self.disconnected {
+            return;
+        }
+        self.time = self.time.max(time);
+
+        if let Some(transform) = sample(&self.scenario.viewer, self.time) {
+            let _ = self.sender.send(MockDeviceMsg::SetViewerOrigin(transform));
+        }
+
+        for (input, added) in self.scenario.inputs.iter().zip(&mut self.added) {
+            let start = input.keyframes.first().map_or(0.0, |k| k.time);
+            if start > self.time {
+                continue;
+            }
+            let pointer_origin =
+                sample(&input.keyframes, self.time).unwrap_or_else(RigidTransform3D::identity);
+            let msg = if *added {
+                MockDeviceMsg::MessageInputSource(
+                    input.source.id,
+                    MockInputMsg::SetPointerOrigin(pointer_origin),
+                )
+            } else {
+                *added = true;
+                MockDeviceMsg::AddInputSource(MockInputInit {
+                    source: input.source,
+                    pointer_origin,
+                })
+            };
+            let _ = self.sender.send(msg);
+        }
+
+        while let Some(event) = self.scenario.events.get(self.next_event) {
+            if event.time > self.time {
+                break;
+            }
+            self.next_event += 1;
+            let event = event.event;
+            self.send_event(event);
+            if self.disconnected {
+                return;
+            }
+        }
+    }
+
+    /// Run the whole scenario in real time, sending poses at the given interval.
+    pub fn run(mut self, interval: Duration) {
+        let start = Instant::now();
+        loop {
+            let elapsed = start.elapsed();
+            let time =
+                elapsed.as_secs() as f64 * 1000.0 + f64::from(elapsed.subsec_nanos()) / 1_000_000.0;
+            self.advance_to(time);
+            if self.finished() {
+                break;
+            }
+            thread::sleep(interval);
+        }
+    }
+
+    fn send_event(&mut self, event: ScenarioEvent) {
+        let msg = match event {
+            ScenarioEvent::Select(id, kind, event) => {
+                MockDeviceMsg::MessageInputSource(id, MockInputMsg::TriggerSelect(kind, event))
+            }
+            ScenarioEvent::DisconnectInput(id) => {
+                MockDeviceMsg::MessageInputSource(id, MockInputMsg::Disconnect)
+            }
+            ScenarioEvent::ReconnectInput(id) => {
+                MockDeviceMsg::MessageInputSource(id, MockInputMsg::Reconnect)
+            }
+            ScenarioEvent::Focus => MockDeviceMsg::Focus,
+            ScenarioEvent::Blur => MockDeviceMsg::Blur,
+            ScenarioEvent::Disconnect => {
+                self.disconnected = true;
+                if let Ok((sender, receiver)) = webxr_api::channel() {
+                    let _ = self.sender.send(MockDeviceMsg::Disconnect(sender));
+                    let _ = receiver.recv();
+                }
+                return;
+            }
+        };
+        let _ = self.sender.send(msg);
+    }
+}
+
+/// The pose at the given time, interpolating between the keyframes either side of it.
+fn sample<Src>(keyframes: &[Keyframe], time: f64) -> Option<RigidTransform3D<f32, Src, Native>> {
+    let next = keyframes.iter().position(|k| k.time > time);
+    match next {
+        Some(0) => keyframes.first().map(|k| k.pose.transform()),
+        Some(index) => {
+            let (a, b) = (&keyframes[index - 1], &keyframes[index]);
+            let t = ((time - a.time) / (b.time - a.time)) as f32;
+            let (a, b): (RigidTransform3D<f32, Src, Native>, _) =
+                (a.pose.transform(), b.pose.transform());
+            Some(RigidTransform3D::new(
+                a.rotation.slerp(&b.rotation, t),
+                a.translation.lerp(b.translation, t),
+            ))
+        }
+        None => keyframes.last().map(|k| k.pose.transform()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::sample;
+    use super::Keyframe;
+    use super::Pose;
+    use super::Scenario;
+    use super::ScenarioEvent;
+    use super::ScenarioRunner;
+    use super::TimedEvent;
+
+    use webxr_api::InputId;
+    use webxr_api::MockDeviceMsg;
+    use webxr_api::Viewer;
+    use webxr_api::Views;
+
+    use euclid::Transform3D;
+    use euclid::Vector3D;
+
+    use std::io;
+
+    const JSON: &str = r#"{
+        "device": {
+            "viewer_origin": {"position": [0, 1.6, 0]},
+            "supports_immersive": true,
+            "views": {"Mono": {
+                "projection": [1, 0, 0, 0, 0, 1, 0, 0, 0, 0, 1, 0, 0, 0, 0, 1],
+                "viewport": {"origin": [0, 0], "size": [1, 1]}
+            }}
+        },
+        "viewer": [{"time": 0, "pose": {}}, {"time": 100, "pose": {"position": [10, 0, 0]}}],
+        "inputs": [{
+            "source": {"handedness": "Right", "target_ray_mode": "TrackedPointer", "id": 1},
+            "keyframes": [{"time": 20, "pose": {"position": [0, 1, 0]}}]
+        }],
+        "events": [{"time": 50, "event": {"Select": [1, "Select", "Start"]}}, {"time": 60, "event": "Blur"}]
+    }"#;
+
+    fn keyframe(time: f64, x: f32) -> Keyframe {
+        let pose = Pose {
+            position: [x, 0.0, 0.0],
+            orientation: [0.0, 0.0, 0.0, 1.0],
+        };
+        Keyframe { time, pose }
+    }
+
+    fn x(keyframes: &[Keyframe], time: f64) -> Option<f32> {
+        sample::<Viewer>(keyframes, time).map(|t| t.translation.x)
+    }
+
+    #[test]
+    fn from_json() {
+        let scenario = Scenario::from_json(JSON).unwrap();
+        let viewer_origin = scenario.device.viewer_origin.translation;
+        assert_eq!(viewer_origin, Vector3D::new(0.0, 1.6, 0.0));
+        assert!(!scenario.device.supports_unbounded);
+        match scenario.device.views {
+            Views::Mono(ref view) => assert_eq!(view.projection, Transform3D::identity()),
+            Views::Stereo(..) => panic!("Views should be mono"),
+        }
+        assert_eq!(scenario.viewer.len(), 2);
+        assert_eq!(scenario.inputs[0].source.id, InputId(1));
+        assert_eq!(scenario.inputs[0].keyframes[0].pose.orientation[3], 1.0);
+        assert_eq!(scenario.events.len(), 2);
+        assert_eq!(scenario.duration(), 100.0);
+
+        // Devices are written back with poses, so they can be read again.
+        let json = serde_json::to_string(&scenario).unwrap();
+        let scenario = Scenario::from_json(&json).unwrap();
+        let translation = scenario.device.viewer_origin.translation;
+        assert_eq!(translation, viewer_origin);
+
+        let error = Scenario::from_json("{}").unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn sample_interpolates_between_keyframes() {
+        let keyframes = [
+            keyframe(0.0, 0.0),
+            keyframe(100.0, 10.0),
+            keyframe(200.0, 0.0),
+        ];
+        assert_eq!(x(&keyframes, -10.0), Some(0.0));
+        assert_eq!(x(&keyframes, 25.0), Some(2.5));
+        assert_eq!(x(&keyframes, 100.0), Some(10.0));
+        assert_eq!(x(&keyframes, 150.0), Some(5.0));
+        assert_eq!(x(&keyframes, 300.0), Some(0.0));
+        assert_eq!(x(&[], 0.0), None);
+    }
+
+    #[test]
+    fn events_are_sent_in_time_order() {
+        let mut scenario = Scenario::from_json(JSON).unwrap();
+        scenario.viewer.clear();
+        scenario.inputs.clear();
+        scenario.events = vec![
+            TimedEvent {
+                time: 30.0,
+                event: ScenarioEvent::Blur,
+            },
+            TimedEvent {
+                time: 10.0,
+                event: ScenarioEvent::Focus,
+            },
+            // `from_json` rejects times which aren't numbers,
+            // but sorting them shouldn't panic.
+            TimedEvent {
+                time: f64::NAN,
+                event: ScenarioEvent::Focus,
+            },
+        ];
+        let (sender, receiver) = webxr_api::channel().unwrap();
+        let mut runner = ScenarioRunner::new(scenario, sender);
+
+        runner.advance_to(5.0);
+        assert!(receiver.try_recv().is_err());
+
+        runner.advance_to(40.0);
+        assert!(matches!(receiver.try_recv(), Ok(MockDeviceMsg::Focus)));
+        assert!(matches!(receiver.try_recv(), Ok(MockDeviceMsg::Blur)));
+    }
+}